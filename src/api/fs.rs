@@ -0,0 +1,262 @@
+use error::Error;
+use function::callable::Callable;
+use scanner::token::TokenKind::*;
+use scanner::token::*;
+use stack::*;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::mem::size_of;
+use std::path::Path;
+use worker::Worker;
+
+/// The `index`-th argument of `fun`, which must be a String.
+fn string_arg<'a>(
+    fun: &str,
+    paren: &Token,
+    arguments: &'a [Instance],
+    index: usize,
+) -> Result<&'a str, Error> {
+    match arguments.get(index).unwrap_or(&Instance::Nil) {
+        Instance::String(s) => Ok(s),
+        other => Err(Error::new(
+            paren.line,
+            format!(
+                "{} expected type 'String', found '{:?}' instead.",
+                fun, other
+            ),
+        )),
+    }
+}
+
+fn io_error(fun: &str, paren: &Token, path: &str, error: &std::io::Error) -> Error {
+    Error::new(
+        paren.line,
+        format!("{} failed on '{}': {}.", fun, path, error),
+    )
+}
+
+/// `read_file(path)`: the whole content of a file as a String.
+#[derive(Debug, Clone)]
+struct ReadFileFun {}
+
+impl Callable for ReadFileFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let path = string_arg("read_file", paren, arguments, 0)?;
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Instance::String(content)),
+            Err(e) => Err(io_error("read_file", paren, path, &e)),
+        }
+    }
+}
+
+/// `read_lines(path)`: the lines of a file, to be looped over with `for`.
+#[derive(Debug, Clone)]
+struct ReadLinesFun {}
+
+impl Callable for ReadLinesFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let path = string_arg("read_lines", paren, arguments, 0)?;
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Instance::Function(Box::new(Strings {
+                kind: "lines",
+                items: content.lines().map(String::from).collect(),
+            }))),
+            Err(e) => Err(io_error("read_lines", paren, path, &e)),
+        }
+    }
+}
+
+/// `write_file(path, text)` and `append_file(path, text)`.
+#[derive(Debug, Clone)]
+struct WriteFileFun {
+    append: bool,
+}
+
+impl Callable for WriteFileFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let fun = if self.append {
+            "append_file"
+        } else {
+            "write_file"
+        };
+        let path = string_arg(fun, paren, arguments, 0)?;
+        let text = string_arg(fun, paren, arguments, 1)?;
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| io_error(fun, paren, path, &e))?;
+        Ok(Instance::Nil)
+    }
+}
+
+/// `exists(path)`: whether a file or directory exists at `path`.
+#[derive(Debug, Clone)]
+struct ExistsFun {}
+
+impl Callable for ExistsFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let path = string_arg("exists", paren, arguments, 0)?;
+        Ok(Instance::Bool(Path::new(path).exists()))
+    }
+}
+
+/// `list_dir(path)`: the sorted names of the entries in a directory.
+#[derive(Debug, Clone)]
+struct ListDirFun {}
+
+impl Callable for ListDirFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let path = string_arg("list_dir", paren, arguments, 0)?;
+        let entries = fs::read_dir(path).map_err(|e| io_error("list_dir", paren, path, &e))?;
+        let mut items = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| io_error("list_dir", paren, path, &e))?;
+            items.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        items.sort();
+        Ok(Instance::Function(Box::new(Strings {
+            kind: "directory listing",
+            items,
+        })))
+    }
+}
+
+/// A list of strings read from the file system, iterable with `for`.
+#[derive(Debug, Clone)]
+struct Strings {
+    kind: &'static str,
+    items: Vec<String>,
+}
+
+impl Callable for Strings {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        _arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        Err(Error::new(
+            paren.line,
+            format!("{} object is not callable.", self.kind),
+        ))
+    }
+
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = Instance>>> {
+        Some(Box::new(
+            self.items.clone().into_iter().map(Instance::String),
+        ))
+    }
+
+    fn heap_size(&self) -> usize {
+        self.items
+            .iter()
+            .map(|item| size_of::<String>() + item.len())
+            .sum()
+    }
+}
+
+pub fn load_fs_api(stack: &mut Stack) -> Result<(), Error> {
+    stack.define(
+        &Token::new(Identifier(String::from("read_file")), 0),
+        Instance::Function(Box::new(ReadFileFun {})),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("read_lines")), 0),
+        Instance::Function(Box::new(ReadLinesFun {})),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("write_file")), 0),
+        Instance::Function(Box::new(WriteFileFun { append: false })),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("append_file")), 0),
+        Instance::Function(Box::new(WriteFileFun { append: true })),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("exists")), 0),
+        Instance::Function(Box::new(ExistsFun {})),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("list_dir")), 0),
+        Instance::Function(Box::new(ListDirFun {})),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use module::compile;
+    use std::env;
+
+    fn try_run(code: &str) -> Result<Stack, Error> {
+        let stmts = compile(code.into())?;
+        let mut stack = Stack::default();
+        load_fs_api(&mut stack)?;
+        Worker::new(&mut stack).run(&stmts)?;
+        Ok(stack)
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let dir = env::temp_dir().join("wendell_fs_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("out.txt");
+        let code = format!(
+            "
+            var path = \"{}\";
+            write_file(path, \"one\n\");
+            append_file(path, \"two\n\");
+            var text = read_file(path);
+            var lines = \"\";
+            for (line in read_lines(path)) lines = lines + line + \",\";
+            var found = exists(path);
+            var names = \"\";
+            for (name in list_dir(\"{}\")) names = names + name;
+            ",
+            file.display(),
+            dir.display()
+        );
+        let stack = try_run(&code).unwrap();
+        let get = |name: &str| stack.get(&Token::new(Identifier(name.into()), 0)).unwrap();
+        assert_eq!(get("text").to_string(), "one\ntwo\n");
+        assert_eq!(get("lines").to_string(), "one,two,");
+        assert_eq!(get("found").to_string(), "true");
+        assert_eq!(get("names").to_string(), "out.txt");
+
+        let missing = dir.join("missing.txt");
+        let code = format!("\n\nread_file(\"{}\");", missing.display());
+        let error = try_run(&code).err().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(error.msg.starts_with("read_file failed on"));
+    }
+}
@@ -1,3 +1,7 @@
+pub mod fs;
+pub mod process;
+pub mod time;
+
 use error::Error;
 use function::callable::Callable;
 use scanner::token::TokenKind::*;
@@ -23,9 +27,86 @@ impl Callable for TestFun {
     }
 }
 
+#[derive(Debug, Clone)]
+struct RangeFun {}
+
+impl Callable for RangeFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        let mut numbers = Vec::new();
+        for arg in arguments {
+            match arg {
+                Instance::Number(n) => numbers.push(*n),
+                other => {
+                    return Err(Error::new(
+                        paren.line,
+                        format!("range expected type 'Number', found '{:?}' instead.", other),
+                    ))
+                }
+            }
+        }
+
+        let (start, end, step) = match numbers[..] {
+            [end] => (0.0, end, 1.0),
+            [start, end] => (start, end, 1.0),
+            [start, end, step] => (start, end, step),
+            _ => {
+                return Err(Error::new(
+                    paren.line,
+                    format!("range expected 1 to 3 arguments, found {}.", numbers.len()),
+                ))
+            }
+        };
+        if step == 0.0 {
+            return Err(Error::new(paren.line, "range step cannot be 0.".into()));
+        }
+
+        Ok(Instance::Function(Box::new(Range { start, end, step })))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Range {
+    start: f64,
+    end: f64,
+    step: f64,
+}
+
+impl Callable for Range {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        _arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        Err(Error::new(
+            paren.line,
+            "range object is not callable.".into(),
+        ))
+    }
+
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = Instance>>> {
+        let Range { start, end, step } = *self;
+        // a u64 count cannot run out before the bound is passed
+        let iter = (0u64..)
+            .map(move |i| start + step * i as f64)
+            .take_while(move |n| if step > 0.0 { *n < end } else { *n > end })
+            .map(Instance::Number);
+        Some(Box::new(iter))
+    }
+}
+
 pub fn load_std_api(stack: &mut Stack) -> Result<(), Error> {
     stack.define(
         &Token::new(Identifier(String::from("test")), 0),
         Instance::Function(Box::new(TestFun {})),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("range")), 0),
+        Instance::Function(Box::new(RangeFun {})),
     )
 }
@@ -0,0 +1,126 @@
+use error::Error;
+use function::callable::Callable;
+use scanner::token::TokenKind::*;
+use scanner::token::*;
+use stack::*;
+use std::env;
+use worker::Worker;
+
+/// `argc()`: number of arguments passed to the script.
+#[derive(Debug, Clone)]
+struct ArgcFun {
+    args: Vec<String>,
+}
+
+impl Callable for ArgcFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        _paren: &Token,
+        _arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        Ok(Instance::Number(self.args.len() as f64))
+    }
+}
+
+/// `argv(i)`: the i-th argument passed to the script, or nil.
+#[derive(Debug, Clone)]
+struct ArgvFun {
+    args: Vec<String>,
+}
+
+impl Callable for ArgvFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        match arguments.first().unwrap_or(&Instance::Nil) {
+            Instance::Number(n) if *n >= 0.0 && n.fract() == 0.0 => {
+                match self.args.get(*n as usize) {
+                    Some(arg) => Ok(Instance::String(arg.clone())),
+                    None => Ok(Instance::Nil),
+                }
+            }
+            other => Err(Error::new(
+                paren.line,
+                format!(
+                    "argv expected a non-negative integer, found '{:?}' instead.",
+                    other
+                ),
+            )),
+        }
+    }
+}
+
+/// `env(name)`: the value of an environment variable, or nil when unset.
+#[derive(Debug, Clone)]
+struct EnvFun {}
+
+impl Callable for EnvFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        match arguments.first().unwrap_or(&Instance::Nil) {
+            Instance::String(name) => match env::var(name) {
+                Ok(value) => Ok(Instance::String(value)),
+                Err(_) => Ok(Instance::Nil),
+            },
+            other => Err(Error::new(
+                paren.line,
+                format!("env expected type 'String', found '{:?}' instead.", other),
+            )),
+        }
+    }
+}
+
+/// `exit(code)`: stops the script, making the interpreter exit with `code`.
+#[derive(Debug, Clone)]
+struct ExitFun {}
+
+impl Callable for ExitFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        match arguments.first().unwrap_or(&Instance::Nil) {
+            Instance::Nil => Err(Error::exit(paren.line, 0)),
+            Instance::Number(n) if n.fract() == 0.0 && n.abs() <= f64::from(i32::MAX) => {
+                Err(Error::exit(paren.line, *n as i32))
+            }
+            other => Err(Error::new(
+                paren.line,
+                format!("exit expected an integer, found '{:?}' instead.", other),
+            )),
+        }
+    }
+}
+
+pub fn load_process_api(stack: &mut Stack, args: &[String]) -> Result<(), Error> {
+    stack.define(
+        &Token::new(Identifier(String::from("argc")), 0),
+        Instance::Function(Box::new(ArgcFun {
+            args: args.to_vec(),
+        })),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("argv")), 0),
+        Instance::Function(Box::new(ArgvFun {
+            args: args.to_vec(),
+        })),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("env")), 0),
+        Instance::Function(Box::new(EnvFun {})),
+    )?;
+    stack.define(
+        &Token::new(Identifier(String::from("exit")), 0),
+        Instance::Function(Box::new(ExitFun {})),
+    )
+}
@@ -0,0 +1,32 @@
+use error::Error;
+use function::callable::Callable;
+use scanner::token::TokenKind::*;
+use scanner::token::*;
+use stack::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use worker::Worker;
+
+/// `clock()`: seconds since the Unix epoch, with a fractional part.
+#[derive(Debug, Clone)]
+struct ClockFun {}
+
+impl Callable for ClockFun {
+    fn call(
+        &self,
+        _worker: &mut Worker,
+        paren: &Token,
+        _arguments: &[Instance],
+    ) -> Result<Instance, Error> {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => Ok(Instance::Number(elapsed.as_secs_f64())),
+            Err(e) => Err(Error::new(paren.line, format!("clock failed: {}.", e))),
+        }
+    }
+}
+
+pub fn load_time_api(stack: &mut Stack) -> Result<(), Error> {
+    stack.define(
+        &Token::new(Identifier(String::from("clock")), 0),
+        Instance::Function(Box::new(ClockFun {})),
+    )
+}
@@ -0,0 +1,24 @@
+use ast::expr::Expr;
+use ast::stmt::Stmt;
+
+/// One arm of a `match` statement. An arm without patterns is the default arm.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub patterns: Vec<Expr>,
+    pub guard: Option<Expr>,
+    pub body: Stmt,
+}
+
+impl MatchArm {
+    pub fn new(patterns: Vec<Expr>, guard: Option<Expr>, body: Stmt) -> Self {
+        Self {
+            patterns,
+            guard,
+            body,
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
@@ -1,3 +1,4 @@
+use ast::stmt::Stmt;
 use scanner::token::Token;
 
 #[derive(Debug, Clone)]
@@ -36,6 +37,17 @@ pub enum Expr {
         name: Token,
     },
 
+    Lambda {
+        keyword: Token,
+        params: Vec<Token>,
+        body: Box<Stmt>,
+    },
+
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+
     Empty,
 }
 
@@ -63,6 +75,15 @@ impl Expr {
                 ref arguments,
             } => visitor.visit_call(callee, paren, arguments),
             Expr::Variable { ref name } => visitor.visit_variable(name),
+            Expr::Lambda {
+                ref keyword,
+                ref params,
+                ref body,
+            } => visitor.visit_lambda(keyword, params, body),
+            Expr::Get {
+                ref object,
+                ref name,
+            } => visitor.visit_get(object, name),
             Expr::Empty => visitor.visit_empty_expr(),
         }
     }
@@ -102,6 +123,18 @@ impl Expr {
     pub fn new_variable(name: Token) -> Self {
         Expr::Variable { name }
     }
+
+    pub fn new_lambda(keyword: Token, params: Vec<Token>, body: Box<Stmt>) -> Self {
+        Expr::Lambda {
+            keyword,
+            params,
+            body,
+        }
+    }
+
+    pub fn new_get(object: Box<Expr>, name: Token) -> Self {
+        Expr::Get { object, name }
+    }
 }
 
 pub trait ExprVisitor<R> {
@@ -112,5 +145,7 @@ pub trait ExprVisitor<R> {
     fn visit_unary(&mut self, operator: &Token, right: &Expr) -> R;
     fn visit_call(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> R;
     fn visit_variable(&mut self, name: &Token) -> R;
+    fn visit_lambda(&mut self, keyword: &Token, params: &[Token], body: &Stmt) -> R;
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> R;
     fn visit_empty_expr(&mut self) -> R;
 }
@@ -0,0 +1,56 @@
+use ast::expr::Expr;
+use ast::stmt::Stmt;
+
+impl Expr {
+    /// The line the expression starts on, if it has any tokens.
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            Expr::Assign { name, .. } => Some(name.line),
+            Expr::Binary { left, operator, .. } => left.line().or(Some(operator.line)),
+            Expr::Grouping { expression } => expression.line(),
+            Expr::Literal { value } => Some(value.line),
+            Expr::Unary { operator, .. } => Some(operator.line),
+            Expr::Call { callee, paren, .. } => callee.line().or(Some(paren.line)),
+            Expr::Variable { name } => Some(name.line),
+            Expr::Lambda { keyword, .. } => Some(keyword.line),
+            Expr::Get { object, name } => object.line().or(Some(name.line)),
+            Expr::Empty => None,
+        }
+    }
+
+    /// The column the expression starts at, if it has any tokens.
+    pub fn column(&self) -> Option<u32> {
+        match self {
+            Expr::Assign { name, .. } => Some(name.column),
+            Expr::Binary { left, operator, .. } => left.column().or(Some(operator.column)),
+            Expr::Grouping { expression } => expression.column(),
+            Expr::Literal { value } => Some(value.column),
+            Expr::Unary { operator, .. } => Some(operator.column),
+            Expr::Call { callee, paren, .. } => callee.column().or(Some(paren.column)),
+            Expr::Variable { name } => Some(name.column),
+            Expr::Lambda { keyword, .. } => Some(keyword.column),
+            Expr::Get { object, name } => object.column().or(Some(name.column)),
+            Expr::Empty => None,
+        }
+    }
+}
+
+impl Stmt {
+    /// The line the statement starts on, if it has any tokens.
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            Stmt::Var { name, .. } | Stmt::Const { name, .. } => Some(name.line),
+            Stmt::Import { keyword, .. } => Some(keyword.line),
+            Stmt::Block { statements } => statements.iter().find_map(Stmt::line),
+            Stmt::If { line_number, .. } | Stmt::While { line_number, .. } => Some(*line_number),
+            Stmt::ForIn { name, .. } => Some(name.line),
+            Stmt::Match { keyword, .. } => Some(keyword.line),
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => Some(keyword.line),
+            Stmt::Function { name, .. } => Some(name.line),
+            Stmt::Return { keyword, .. } | Stmt::Throw { keyword, .. } => Some(keyword.line),
+            Stmt::Try { keyword, .. } => Some(keyword.line),
+            Stmt::Expression { expression } | Stmt::Print { expression } => expression.line(),
+            Stmt::Empty => None,
+        }
+    }
+}
@@ -1,2 +1,4 @@
+pub mod arm;
 pub mod expr;
+mod line;
 pub mod stmt;
@@ -1,3 +1,4 @@
+use ast::arm::MatchArm;
 use ast::expr::Expr;
 use scanner::token::Token;
 
@@ -8,6 +9,16 @@ pub enum Stmt {
         initializer: Expr,
     },
 
+    Const {
+        name: Token,
+        initializer: Expr,
+    },
+
+    Import {
+        keyword: Token,
+        path: Token,
+    },
+
     Block {
         statements: Vec<Stmt>,
     },
@@ -23,6 +34,27 @@ pub enum Stmt {
         line_number: u32,
         condition: Expr,
         body: Box<Stmt>,
+        increment: Box<Stmt>,
+    },
+
+    ForIn {
+        name: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+
+    Match {
+        keyword: Token,
+        value: Expr,
+        arms: Vec<MatchArm>,
+    },
+
+    Break {
+        keyword: Token,
+    },
+
+    Continue {
+        keyword: Token,
     },
 
     Function {
@@ -31,6 +63,24 @@ pub enum Stmt {
         body: Box<Stmt>,
     },
 
+    Return {
+        keyword: Token,
+        value: Expr,
+    },
+
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+
+    Try {
+        keyword: Token,
+        body: Box<Stmt>,
+        catch_name: Option<Token>,
+        catch_body: Box<Stmt>,
+        finally_body: Box<Stmt>,
+    },
+
     Expression {
         expression: Expr,
     },
@@ -49,6 +99,14 @@ impl Stmt {
                 ref name,
                 ref initializer,
             } => visitor.visit_var(name, initializer),
+            Stmt::Const {
+                ref name,
+                ref initializer,
+            } => visitor.visit_const(name, initializer),
+            Stmt::Import {
+                ref keyword,
+                ref path,
+            } => visitor.visit_import(keyword, path),
             Stmt::Block { ref statements } => visitor.visit_block(statements),
             Stmt::If {
                 ref line_number,
@@ -60,12 +118,40 @@ impl Stmt {
                 ref line_number,
                 ref condition,
                 ref body,
-            } => visitor.visit_while(line_number, condition, body),
+                ref increment,
+            } => visitor.visit_while(line_number, condition, body, increment),
+            Stmt::ForIn {
+                ref name,
+                ref iterable,
+                ref body,
+            } => visitor.visit_forin(name, iterable, body),
+            Stmt::Match {
+                ref keyword,
+                ref value,
+                ref arms,
+            } => visitor.visit_match(keyword, value, arms),
+            Stmt::Break { ref keyword } => visitor.visit_break(keyword),
+            Stmt::Continue { ref keyword } => visitor.visit_continue(keyword),
             Stmt::Function {
                 ref name,
                 ref params,
                 ref body,
             } => visitor.visit_function(name, params, body),
+            Stmt::Return {
+                ref keyword,
+                ref value,
+            } => visitor.visit_return(keyword, value),
+            Stmt::Throw {
+                ref keyword,
+                ref value,
+            } => visitor.visit_throw(keyword, value),
+            Stmt::Try {
+                ref keyword,
+                ref body,
+                ref catch_name,
+                ref catch_body,
+                ref finally_body,
+            } => visitor.visit_try(keyword, body, catch_name, catch_body, finally_body),
             Stmt::Expression { ref expression } => visitor.visit_expression(expression),
             Stmt::Print { ref expression } => visitor.visit_print(expression),
             Stmt::Empty => visitor.visit_empty_stmt(),
@@ -76,6 +162,14 @@ impl Stmt {
         Stmt::Var { name, initializer }
     }
 
+    pub fn new_const(name: Token, initializer: Expr) -> Self {
+        Stmt::Const { name, initializer }
+    }
+
+    pub fn new_import(keyword: Token, path: Token) -> Self {
+        Stmt::Import { keyword, path }
+    }
+
     pub fn new_block(statements: Vec<Stmt>) -> Self {
         Stmt::Block { statements }
     }
@@ -94,18 +188,72 @@ impl Stmt {
         }
     }
 
-    pub fn new_while(line_number: u32, condition: Expr, body: Box<Stmt>) -> Self {
+    pub fn new_while(
+        line_number: u32,
+        condition: Expr,
+        body: Box<Stmt>,
+        increment: Box<Stmt>,
+    ) -> Self {
         Stmt::While {
             line_number,
             condition,
             body,
+            increment,
+        }
+    }
+
+    pub fn new_forin(name: Token, iterable: Expr, body: Box<Stmt>) -> Self {
+        Stmt::ForIn {
+            name,
+            iterable,
+            body,
         }
     }
 
+    pub fn new_match(keyword: Token, value: Expr, arms: Vec<MatchArm>) -> Self {
+        Stmt::Match {
+            keyword,
+            value,
+            arms,
+        }
+    }
+
+    pub fn new_break(keyword: Token) -> Self {
+        Stmt::Break { keyword }
+    }
+
+    pub fn new_continue(keyword: Token) -> Self {
+        Stmt::Continue { keyword }
+    }
+
     pub fn new_function(name: Token, params: Vec<Token>, body: Box<Stmt>) -> Self {
         Stmt::Function { name, params, body }
     }
 
+    pub fn new_return(keyword: Token, value: Expr) -> Self {
+        Stmt::Return { keyword, value }
+    }
+
+    pub fn new_throw(keyword: Token, value: Expr) -> Self {
+        Stmt::Throw { keyword, value }
+    }
+
+    pub fn new_try(
+        keyword: Token,
+        body: Box<Stmt>,
+        catch_name: Option<Token>,
+        catch_body: Box<Stmt>,
+        finally_body: Box<Stmt>,
+    ) -> Self {
+        Stmt::Try {
+            keyword,
+            body,
+            catch_name,
+            catch_body,
+            finally_body,
+        }
+    }
+
     pub fn new_expression(expression: Expr) -> Self {
         Stmt::Expression { expression }
     }
@@ -117,6 +265,8 @@ impl Stmt {
 
 pub trait StmtVisitor<R> {
     fn visit_var(&mut self, name: &Token, initializer: &Expr) -> R;
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) -> R;
+    fn visit_import(&mut self, keyword: &Token, path: &Token) -> R;
     fn visit_block(&mut self, statements: &[Stmt]) -> R;
     fn visit_if(
         &mut self,
@@ -125,8 +275,28 @@ pub trait StmtVisitor<R> {
         then_block: &Stmt,
         else_block: &Stmt,
     ) -> R;
-    fn visit_while(&mut self, line_number: &u32, condition: &Expr, body: &Stmt) -> R;
+    fn visit_while(
+        &mut self,
+        line_number: &u32,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Stmt,
+    ) -> R;
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> R;
+    fn visit_match(&mut self, keyword: &Token, value: &Expr, arms: &[MatchArm]) -> R;
+    fn visit_break(&mut self, keyword: &Token) -> R;
+    fn visit_continue(&mut self, keyword: &Token) -> R;
     fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt) -> R;
+    fn visit_return(&mut self, keyword: &Token, value: &Expr) -> R;
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> R;
+    fn visit_try(
+        &mut self,
+        keyword: &Token,
+        body: &Stmt,
+        catch_name: &Option<Token>,
+        catch_body: &Stmt,
+        finally_body: &Stmt,
+    ) -> R;
     fn visit_expression(&mut self, expression: &Expr) -> R;
     fn visit_print(&mut self, expression: &Expr) -> R;
     fn visit_empty_stmt(&mut self) -> R;
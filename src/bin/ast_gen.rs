@@ -13,8 +13,10 @@ pub fn main() {
         "Unary    : Token operator, Box<Expr> right",
         "Call     : Box<Expr> callee, Token paren, Vec<Expr> arguments",
         "Variable : Token name",
+        "Lambda   : Token keyword, Vec<Token> params, Box<Stmt> body",
+        "Get      : Box<Expr> object, Token name",
     ];
-    let expr_atoms = ["scanner::token::Token"];
+    let expr_atoms = ["ast::stmt::Stmt", "scanner::token::Token"];
     if let Err(e) = define_ast(output_dir, "Expr", &expr_atoms, &expr_nodes) {
         println!("{:?}", e);
     }
@@ -22,16 +24,29 @@ pub fn main() {
     let stmt_nodes = [
         // declaration
         "Var        : Token name, Expr initializer",
+        "Const      : Token name, Expr initializer",
+        "Import     : Token keyword, Token path",
         // statement
         "Block      : Vec<Stmt> statements",
         "If         : u32 line_number, Expr condition, Box<Stmt> then_block, Box<Stmt> else_block",
-        "While      : u32 line_number, Expr condition, Box<Stmt> body",
+        "While      : u32 line_number, Expr condition, Box<Stmt> body, Box<Stmt> increment",
+        "ForIn      : Token name, Expr iterable, Box<Stmt> body",
+        "Match      : Token keyword, Expr value, Vec<MatchArm> arms",
+        "Break      : Token keyword",
+        "Continue   : Token keyword",
         "Function   : Token name, Vec<Token> params, Box<Stmt> body",
+        "Return     : Token keyword, Expr value",
+        "Throw      : Token keyword, Expr value",
+        "Try        : Token keyword, Box<Stmt> body, Option<Token> catch_name, Box<Stmt> catch_body, Box<Stmt> finally_body",
         "Expression : Expr expression",
         // function stand-in (remove later)
         "Print      : Expr expression",
     ];
-    let stmt_atoms = ["ast::expr::Expr", "scanner::token::Token"];
+    let stmt_atoms = [
+        "ast::arm::MatchArm",
+        "ast::expr::Expr",
+        "scanner::token::Token",
+    ];
     if let Err(e) = define_ast(output_dir, "Stmt", &stmt_atoms, &stmt_nodes) {
         println!("{:?}", e);
     }
@@ -84,7 +99,7 @@ impl<T: Write> AstWriter<T> {
         for node in nodes.iter() {
             self.define_node(node)?;
         }
-        self.println("    Empty,".to_string())?;
+        self.println("    Empty,")?;
         self.println("}")?;
         self.println("")
     }
@@ -0,0 +1,11 @@
+extern crate wendell;
+
+use std::io::{self, BufReader};
+use wendell::dap::DapServer;
+
+/// Debugs wendell scripts for an editor, speaking the Debug Adapter Protocol
+/// over stdin and stdout.
+fn main() {
+    let input = Box::new(BufReader::new(io::stdin()));
+    DapServer::new(input, Box::new(io::stdout())).run();
+}
@@ -0,0 +1,102 @@
+extern crate wendell;
+
+mod options;
+
+use options::*;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Read};
+use std::process;
+use wendell::interpreter::{Interpreter, RunError};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let Options {
+        source,
+        script_args,
+        search_paths,
+        debug,
+        profile,
+        profile_stacks,
+        coverage,
+    } = match parse_args(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => return,
+        Err(msg) => {
+            eprintln!("interp: {}", msg);
+            eprintln!("{}", USAGE);
+            process::exit(EX_USAGE);
+        }
+    };
+
+    let mut intr = Interpreter::new(&script_args);
+    for path in search_paths {
+        intr.add_search_path(path);
+    }
+    if debug {
+        intr.enable_debugger();
+    }
+    if profile {
+        intr.enable_profiler();
+    }
+    if let Some(ref path) = coverage {
+        match fs::read_to_string(path) {
+            Ok(lcov) => {
+                if let Err(msg) = intr.merge_coverage(&lcov) {
+                    eprintln!("interp: cannot read '{}': {}", path, msg);
+                    process::exit(EX_DATAERR);
+                }
+            }
+            // the first run of a suite
+            Err(ref e) if e.kind() == ErrorKind::NotFound => intr.enable_coverage(),
+            Err(e) => {
+                eprintln!("interp: cannot read '{}': {}", path, e);
+                process::exit(EX_NOINPUT);
+            }
+        }
+    }
+    let result = match source {
+        Source::Prompt => {
+            println!("wendell {} interpreter.", env!("CARGO_PKG_VERSION"));
+            println!("Type :help for commands, :quit or Ctrl^Z to exit.");
+            intr.run_prompt()
+        }
+        Source::File(file_name) => intr.run_file(&file_name),
+        Source::Stdin => {
+            let mut code = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut code) {
+                eprintln!("interp: cannot read script from stdin: {}", e);
+                process::exit(EX_NOINPUT);
+            }
+            intr.run_source(code)
+        }
+        Source::Code(code) => intr.run_source(code),
+    };
+
+    if let Some(report) = intr.profile_report() {
+        eprint!("\n{}", report);
+    }
+    if let (Some(path), Some(stacks)) = (profile_stacks, intr.profile_stacks()) {
+        if let Err(e) = fs::write(&path, stacks) {
+            eprintln!("interp: cannot write '{}': {}", path, e);
+            process::exit(EX_CANTCREAT);
+        }
+    }
+    if let (Some(path), Some(lcov)) = (coverage, intr.coverage_lcov()) {
+        if let Some(summary) = intr.coverage_summary() {
+            eprint!("\n{}", summary);
+        }
+        if let Err(e) = fs::write(&path, lcov) {
+            eprintln!("interp: cannot write '{}': {}", path, e);
+            process::exit(EX_CANTCREAT);
+        }
+    }
+
+    if let Err(e) = result {
+        if let RunError::Io { .. } = e {
+            eprintln!("interp: {}", e);
+        }
+        process::exit(exit_code(&e));
+    }
+}
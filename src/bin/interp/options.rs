@@ -0,0 +1,237 @@
+use std::env;
+use std::path::PathBuf;
+use wendell::interpreter::RunError;
+
+// Exit codes from sysexits.h
+pub const EX_USAGE: i32 = 64;
+pub const EX_DATAERR: i32 = 65;
+pub const EX_NOINPUT: i32 = 66;
+pub const EX_SOFTWARE: i32 = 70;
+pub const EX_CANTCREAT: i32 = 73;
+// 128 + SIGINT, as shells report a process stopped by Ctrl-C
+pub const EX_INTERRUPTED: i32 = 130;
+
+pub const USAGE: &str = "\
+Usage: interp [options] [script | -] [args...]
+
+Runs the REPL console when no script is given.
+
+Options:
+  -e <code>      Run <code> instead of a script file
+  -I <dir>       Also look for imported modules in <dir>
+  --debug        Run under a step debugger; type 'help' when it pauses
+  --profile      Print call counts, times and the most run lines to stderr
+  --profile-stacks <file>
+                 Also write the time of each call stack to <file>, in the
+                 collapsed format of flame graph tools
+  --coverage <file>
+                 Add the runs of each line and branch to the lcov report in
+                 <file> and print the lines never run to stderr
+  -h, --help     Print this help and exit
+  -V, --version  Print the version and exit
+
+'-' reads the script from stdin. Arguments after the script (or after
+'-e <code>') are passed through to it and can be read with argc()/argv(i).
+Scripts can read environment variables with env(name) and stop with
+exit(code), which becomes the exit status of interp.";
+
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Prompt,
+    File(String),
+    Stdin,
+    Code(String),
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub source: Source,
+    pub script_args: Vec<String>,
+    pub search_paths: Vec<PathBuf>,
+    pub debug: bool,
+    pub profile: bool,
+    pub profile_stacks: Option<String>,
+    pub coverage: Option<String>,
+}
+
+/// Splits the command line into what to run and the arguments passed to the
+/// script. Returns `None` when the command was fully handled (help, version).
+pub fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut search_paths = Vec::new();
+    let mut debug = false;
+    let mut profile = false;
+    let mut profile_stacks = None;
+    let mut coverage = None;
+    let mut iter = args.iter();
+    let source = loop {
+        let arg = match iter.next() {
+            Some(arg) => arg.as_str(),
+            None => break Source::Prompt,
+        };
+        match arg {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(None);
+            }
+            "-V" | "--version" => {
+                println!("wendell {}", env!("CARGO_PKG_VERSION"));
+                return Ok(None);
+            }
+            "-I" => match iter.next() {
+                Some(dir) => search_paths.push(PathBuf::from(dir)),
+                None => return Err("option '-I' requires an argument".into()),
+            },
+            "--debug" => debug = true,
+            "--profile" => profile = true,
+            "--profile-stacks" => match iter.next() {
+                Some(path) => {
+                    profile = true;
+                    profile_stacks = Some(path.clone());
+                }
+                None => return Err("option '--profile-stacks' requires an argument".into()),
+            },
+            "--coverage" => match iter.next() {
+                Some(path) => coverage = Some(path.clone()),
+                None => return Err("option '--coverage' requires an argument".into()),
+            },
+            "-e" => match iter.next() {
+                Some(code) => break Source::Code(code.clone()),
+                None => return Err("option '-e' requires an argument".into()),
+            },
+            "-" => break Source::Stdin,
+            "--" => match iter.next() {
+                Some(file_name) => break Source::File(file_name.clone()),
+                None => break Source::Prompt,
+            },
+            opt if opt.starts_with('-') => return Err(format!("unknown option '{}'", opt)),
+            file_name => break Source::File(file_name.into()),
+        }
+    };
+    let tools: Vec<&str> = vec![
+        (debug, "'--debug'"),
+        (profile, "'--profile'"),
+        (coverage.is_some(), "'--coverage'"),
+    ]
+    .into_iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, option)| option)
+    .collect();
+    if tools.len() > 1 {
+        return Err(format!("{} cannot be used together", tools.join(" and ")));
+    }
+    if debug {
+        if let Source::Stdin = source {
+            return Err(
+                "'--debug' reads commands from stdin, so the script cannot come from it".into(),
+            );
+        }
+    }
+    Ok(Some(Options {
+        source,
+        script_args: iter.cloned().collect(),
+        search_paths,
+        debug,
+        profile,
+        profile_stacks,
+        coverage,
+    }))
+}
+
+/// The exit status for a failed run, from sysexits.h where one fits.
+pub fn exit_code(error: &RunError) -> i32 {
+    match error {
+        RunError::Io { .. } => EX_NOINPUT,
+        RunError::Compile => EX_DATAERR,
+        RunError::Runtime | RunError::Budget => EX_SOFTWARE,
+        RunError::Interrupted => EX_INTERRUPTED,
+        RunError::Exit(code) => *code,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args).map(|options| options.unwrap())
+    }
+
+    #[test]
+    fn sources_and_script_args() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.source, Source::Prompt);
+
+        let options = parse(&["-I", "lib", "main.aul", "-e", "x"]).unwrap();
+        assert_eq!(options.source, Source::File("main.aul".into()));
+        assert_eq!(options.search_paths, [PathBuf::from("lib")]);
+        assert_eq!(options.script_args, ["-e", "x"]);
+
+        let options = parse(&["-e", "print 1;", "a"]).unwrap();
+        assert_eq!(options.source, Source::Code("print 1;".into()));
+        assert_eq!(options.script_args, ["a"]);
+
+        let options = parse(&["-", "a"]).unwrap();
+        assert_eq!(options.source, Source::Stdin);
+        assert_eq!(options.script_args, ["a"]);
+
+        let options = parse(&["--", "-script.aul", "--debug"]).unwrap();
+        assert_eq!(options.source, Source::File("-script.aul".into()));
+        assert_eq!(options.script_args, ["--debug"]);
+        assert!(!options.debug);
+        assert_eq!(parse(&["--"]).unwrap().source, Source::Prompt);
+    }
+
+    #[test]
+    fn tool_options() {
+        let options = parse(&["--profile-stacks", "out.folded", "a.aul"]).unwrap();
+        assert!(options.profile);
+        assert_eq!(options.profile_stacks, Some("out.folded".into()));
+        let options = parse(&["--coverage", "cov.info", "a.aul"]).unwrap();
+        assert_eq!(options.coverage, Some("cov.info".into()));
+        assert!(parse(&["--debug", "a.aul"]).unwrap().debug);
+    }
+
+    #[test]
+    fn usage_errors() {
+        let error = |args: &[&str]| parse(args).unwrap_err();
+        assert_eq!(error(&["-I"]), "option '-I' requires an argument");
+        assert_eq!(error(&["-e"]), "option '-e' requires an argument");
+        assert_eq!(
+            error(&["--coverage"]),
+            "option '--coverage' requires an argument"
+        );
+        assert_eq!(
+            error(&["--profile-stacks"]),
+            "option '--profile-stacks' requires an argument"
+        );
+        assert_eq!(error(&["--verbose"]), "unknown option '--verbose'");
+        assert_eq!(
+            error(&["--debug", "--profile", "a.aul"]),
+            "'--debug' and '--profile' cannot be used together"
+        );
+        assert_eq!(
+            error(&["--profile-stacks", "x", "--coverage", "y", "a.aul"]),
+            "'--profile' and '--coverage' cannot be used together"
+        );
+        assert_eq!(
+            error(&["--debug", "-"]),
+            "'--debug' reads commands from stdin, so the script cannot come from it"
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let io_error = RunError::Io {
+            path: "a.aul".into(),
+            error: io::Error::new(io::ErrorKind::NotFound, "not found"),
+        };
+        assert_eq!(exit_code(&io_error), EX_NOINPUT);
+        assert_eq!(exit_code(&RunError::Compile), EX_DATAERR);
+        assert_eq!(exit_code(&RunError::Runtime), EX_SOFTWARE);
+        assert_eq!(exit_code(&RunError::Budget), EX_SOFTWARE);
+        assert_eq!(exit_code(&RunError::Interrupted), EX_INTERRUPTED);
+        assert_eq!(exit_code(&RunError::Exit(3)), 3);
+    }
+}
@@ -0,0 +1,11 @@
+extern crate wendell;
+
+use std::io::{self, BufReader};
+use wendell::lsp::LspServer;
+
+/// Serves editors the Language Server Protocol for wendell files over stdin
+/// and stdout.
+fn main() {
+    let input = Box::new(BufReader::new(io::stdin()));
+    LspServer::new(input, Box::new(io::stdout())).run();
+}
@@ -0,0 +1,518 @@
+use ast::arm::MatchArm;
+use ast::expr::Expr;
+use ast::stmt::Stmt;
+use error::Error;
+use module::compile;
+use scanner::token::TokenKind;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use worker::{Hook, Worker};
+
+/// Names code that does not come from a file in the reports.
+const SCRIPT: &str = "<script>";
+
+/// Counts the runs of each line that holds a statement, so the lines never
+/// reached show up with a count of zero, and of each way out of the branch
+/// points: `if`, `while`, `match`, `and` and `or`.
+#[derive(Default)]
+pub struct Coverage {
+    files: BTreeMap<Option<PathBuf>, FileCounts>,
+}
+
+#[derive(Default)]
+struct FileCounts {
+    // runs of every line with a statement
+    lines: BTreeMap<u32, u64>,
+    // runs of each way out of a branch point, by line and place on the line
+    branches: BTreeMap<(u32, usize), Vec<u64>>,
+    // place on its line of each branch point, by line and column
+    blocks: HashMap<(u32, u32), usize>,
+}
+
+/// The lines with a statement and the branch points of a program, the
+/// latter as line, column and number of ways out.
+#[derive(Default)]
+struct Points {
+    lines: Vec<u32>,
+    branches: Vec<(u32, u32, usize)>,
+}
+
+impl FileCounts {
+    fn add(&mut self, mut points: Points) {
+        for line in points.lines {
+            self.lines.entry(line).or_default();
+        }
+        // points are numbered on their line from left to right, as read back
+        // from an lcov report
+        points.branches.sort_unstable();
+        for (line, column, ways) in points.branches {
+            let next = self.blocks.keys().filter(|(l, _)| *l == line).count();
+            let block = *self.blocks.entry((line, column)).or_insert(next);
+            let counts = self.branches.entry((line, block)).or_default();
+            if counts.len() < ways {
+                counts.resize(ways, 0);
+            }
+        }
+    }
+
+    fn branches_hit(&self) -> usize {
+        self.branches
+            .values()
+            .map(|counts| counts.iter().filter(|taken| **taken > 0).count())
+            .sum()
+    }
+
+    fn branch_count(&self) -> usize {
+        self.branches.values().map(Vec::len).sum()
+    }
+}
+
+impl Coverage {
+    /// Adds the lines and branch points of a program about to run from `file`.
+    pub fn add_program(&mut self, file: Option<PathBuf>, stmts: &[Stmt]) {
+        let mut points = Points::default();
+        for stmt in stmts {
+            stmt_points(stmt, &mut points);
+        }
+        self.files.entry(file).or_default().add(points);
+    }
+
+    /// Adds the counts of an lcov report, e.g. one written by an earlier run.
+    pub fn merge_lcov(&mut self, lcov: &str) -> Result<(), String> {
+        let mut counts = None;
+        for (number, record) in lcov.lines().enumerate() {
+            let invalid = || format!("invalid lcov record on line {}", number + 1);
+            if let Some(file) = record.strip_prefix("SF:") {
+                let file = match file {
+                    SCRIPT => None,
+                    file => Some(PathBuf::from(file)),
+                };
+                counts = Some(self.files.entry(file).or_default());
+            } else if let Some(data) = record.strip_prefix("DA:") {
+                let mut fields = data.split(',');
+                let line = fields.next().and_then(|line| line.parse().ok());
+                let hits = fields.next().and_then(|hits| hits.parse::<u64>().ok());
+                match (counts.as_mut(), line, hits) {
+                    (Some(counts), Some(line), Some(hits)) => {
+                        *counts.lines.entry(line).or_default() += hits;
+                    }
+                    _ => return Err(invalid()),
+                }
+            } else if let Some(data) = record.strip_prefix("BRDA:") {
+                let fields: Vec<&str> = data.split(',').collect();
+                let number = |i: usize| fields.get(i).and_then(|field| field.parse::<usize>().ok());
+                // `-` marks a point that was never reached
+                let taken = match fields.get(3) {
+                    Some(&"-") => Some(0),
+                    Some(taken) => taken.parse::<u64>().ok(),
+                    None => None,
+                };
+                match (counts.as_mut(), number(0), number(1), number(2), taken) {
+                    (Some(counts), Some(line), Some(block), Some(branch), Some(taken)) => {
+                        let ways = counts.branches.entry((line as u32, block)).or_default();
+                        if ways.len() <= branch {
+                            ways.resize(branch + 1, 0);
+                        }
+                        ways[branch] += taken;
+                    }
+                    _ => return Err(invalid()),
+                }
+            } else if record == "end_of_record" {
+                counts = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// The counts in the lcov tracefile format, one record per file.
+    pub fn lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (file, counts) in self.files.iter() {
+            let name = file
+                .as_ref()
+                .map_or(SCRIPT.into(), |file| file.to_string_lossy());
+            writeln!(lcov, "TN:\nSF:{}", name).ok();
+            for ((line, block), ways) in counts.branches.iter() {
+                let reached = ways.iter().any(|taken| *taken > 0);
+                for (branch, taken) in ways.iter().enumerate() {
+                    let taken = if reached {
+                        taken.to_string()
+                    } else {
+                        "-".into()
+                    };
+                    writeln!(lcov, "BRDA:{},{},{},{}", line, block, branch, taken).ok();
+                }
+            }
+            writeln!(lcov, "BRF:{}", counts.branch_count()).ok();
+            writeln!(lcov, "BRH:{}", counts.branches_hit()).ok();
+            for (line, hits) in counts.lines.iter() {
+                writeln!(lcov, "DA:{},{}", line, hits).ok();
+            }
+            writeln!(lcov, "LF:{}", counts.lines.len()).ok();
+            writeln!(lcov, "LH:{}", hit_count(&counts.lines)).ok();
+            writeln!(lcov, "end_of_record").ok();
+        }
+        lcov
+    }
+
+    /// The share of lines and branches run in each file and the lines that
+    /// never ran.
+    pub fn summary(&self) -> String {
+        let cwd = env::current_dir().unwrap_or_default();
+        let mut summary = String::new();
+        writeln!(
+            summary,
+            "{:<32} {:>7} {:>7} {:>7} {:>7}   missed",
+            "file", "lines", "hit", "cover", "branch"
+        )
+        .ok();
+        let (mut total, mut total_hit) = (0, 0);
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (file, counts) in self.files.iter() {
+            let name = file.as_ref().map_or(SCRIPT.into(), |file| {
+                file.strip_prefix(&cwd)
+                    .unwrap_or(file)
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            let lines = &counts.lines;
+            let hit = hit_count(lines);
+            total += lines.len();
+            total_hit += hit;
+            branches += counts.branch_count();
+            branches_hit += counts.branches_hit();
+            let row = format!(
+                "{:<32} {:>7} {:>7} {:>7} {:>7}   {}",
+                name,
+                lines.len(),
+                hit,
+                percent(hit, lines.len()),
+                percent(counts.branches_hit(), counts.branch_count()),
+                missed_ranges(lines)
+            );
+            writeln!(summary, "{}", row.trim_end()).ok();
+        }
+        writeln!(
+            summary,
+            "{:<32} {:>7} {:>7} {:>7} {:>7}",
+            "total",
+            total,
+            total_hit,
+            percent(total_hit, total),
+            percent(branches_hit, branches)
+        )
+        .ok();
+        summary
+    }
+
+    /// The counts of the file the worker is running, set up on first use.
+    fn file(&mut self, worker: &Worker) -> &mut FileCounts {
+        let file = worker.current_file().map(Path::to_path_buf);
+        if !self.files.contains_key(&file) {
+            // an imported module, compiled again for the lines it never runs
+            let stmts = file
+                .as_ref()
+                .and_then(|file| fs::read_to_string(file).ok())
+                .and_then(|code| compile(code).ok())
+                .unwrap_or_default();
+            self.add_program(file.clone(), &stmts);
+        }
+        self.files.entry(file).or_default()
+    }
+}
+
+impl Hook for Coverage {
+    fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error> {
+        // a block counts through its statements
+        if let Stmt::Block { .. } = stmt {
+            return Ok(());
+        }
+        let line = match stmt.line() {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        *self.file(worker).lines.entry(line).or_default() += 1;
+        Ok(())
+    }
+
+    fn branch(&mut self, worker: &Worker, line: u32, column: u32, taken: usize) {
+        let counts = self.file(worker);
+        let block = match counts.blocks.get(&(line, column)) {
+            Some(block) => *block,
+            None => return,
+        };
+        let ways = counts.branches.entry((line, block)).or_default();
+        if ways.len() <= taken {
+            ways.resize(taken + 1, 0);
+        }
+        ways[taken] += 1;
+    }
+}
+
+fn stmt_points(stmt: &Stmt, points: &mut Points) {
+    match stmt {
+        Stmt::Block { statements } => {
+            for stmt in statements {
+                stmt_points(stmt, points);
+            }
+            return;
+        }
+        Stmt::Empty => return,
+        _ => (),
+    }
+    if let Some(line) = stmt.line() {
+        points.lines.push(line);
+    }
+    match stmt {
+        Stmt::Var { initializer, .. } | Stmt::Const { initializer, .. } => {
+            expr_points(initializer, points)
+        }
+        Stmt::If {
+            line_number,
+            condition,
+            then_block,
+            else_block,
+        } => {
+            let column = condition.column().unwrap_or(0);
+            points.branches.push((*line_number, column, 2));
+            expr_points(condition, points);
+            stmt_points(then_block, points);
+            stmt_points(else_block, points);
+        }
+        Stmt::While {
+            line_number,
+            condition,
+            body,
+            increment,
+        } => {
+            let column = condition.column().unwrap_or(0);
+            points.branches.push((*line_number, column, 2));
+            expr_points(condition, points);
+            stmt_points(body, points);
+            stmt_points(increment, points);
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            expr_points(iterable, points);
+            stmt_points(body, points);
+        }
+        Stmt::Match {
+            keyword,
+            value,
+            arms,
+        } => {
+            // without a default arm, a value can match no arm at all
+            let ways = arms.len() + !arms.iter().any(MatchArm::is_default) as usize;
+            points.branches.push((keyword.line, keyword.column, ways));
+            expr_points(value, points);
+            for arm in arms {
+                for pattern in arm.patterns.iter() {
+                    expr_points(pattern, points);
+                }
+                if let Some(ref guard) = arm.guard {
+                    expr_points(guard, points);
+                }
+                stmt_points(&arm.body, points);
+            }
+        }
+        Stmt::Function { body, .. } => stmt_points(body, points),
+        Stmt::Return { value, .. } | Stmt::Throw { value, .. } => expr_points(value, points),
+        Stmt::Try {
+            body,
+            catch_body,
+            finally_body,
+            ..
+        } => {
+            stmt_points(body, points);
+            stmt_points(catch_body, points);
+            stmt_points(finally_body, points);
+        }
+        Stmt::Expression { expression } | Stmt::Print { expression } => {
+            expr_points(expression, points)
+        }
+        Stmt::Import { .. }
+        | Stmt::Break { .. }
+        | Stmt::Continue { .. }
+        | Stmt::Block { .. }
+        | Stmt::Empty => (),
+    }
+}
+
+// Only lambdas hold statements inside an expression, and only `and` and `or`
+// branch.
+fn expr_points(expr: &Expr, points: &mut Points) {
+    match expr {
+        Expr::Assign { value, .. } => expr_points(value, points),
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            if let TokenKind::And | TokenKind::Or = operator.kind {
+                points.branches.push((operator.line, operator.column, 2));
+            }
+            expr_points(left, points);
+            expr_points(right, points);
+        }
+        Expr::Grouping { expression } => expr_points(expression, points),
+        Expr::Unary { right, .. } => expr_points(right, points),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            expr_points(callee, points);
+            for arg in arguments {
+                expr_points(arg, points);
+            }
+        }
+        Expr::Lambda { body, .. } => stmt_points(body, points),
+        Expr::Get { object, .. } => expr_points(object, points),
+        Expr::Literal { .. } | Expr::Variable { .. } | Expr::Empty => (),
+    }
+}
+
+fn hit_count(lines: &BTreeMap<u32, u64>) -> usize {
+    lines.values().filter(|hits| **hits > 0).count()
+}
+
+fn percent(hit: usize, total: usize) -> String {
+    if total == 0 {
+        return "-".into();
+    }
+    format!("{:.1}%", hit as f64 * 100.0 / total as f64)
+}
+
+/// The lines never run, with neighbours joined as in `3-5, 9`. Lines without
+/// a statement in between do not break a range.
+fn missed_ranges(lines: &BTreeMap<u32, u64>) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    let mut extend = false;
+    for (line, hits) in lines {
+        if *hits > 0 {
+            extend = false;
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if extend => range.1 = *line,
+            _ => ranges.push((*line, *line)),
+        }
+        extend = true;
+    }
+    ranges
+        .iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use api::load_std_api;
+    use stack::Stack;
+
+    /// Runs `code` as the script, counting into `coverage`.
+    fn cover(coverage: &mut Coverage, code: &str) {
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+        load_std_api(&mut stack).unwrap();
+        stack.push();
+        coverage.add_program(None, &stmts);
+        Worker::new(&mut stack)
+            .with_hook(coverage)
+            .run(&stmts)
+            .unwrap();
+    }
+
+    #[test]
+    fn lines_and_lcov() {
+        let code = "fun sign(n) {\n\
+                    if (n < 0) {\n\
+                    return -1;\n\
+                    }\n\
+                    return 1;\n\
+                    }\n\
+                    var never = () => 0;\n\
+                    for (i in range(3)) sign(i);";
+        let mut coverage = Coverage::default();
+        cover(&mut coverage, code);
+
+        let lines: Vec<(u32, u64)> = coverage.files[&None]
+            .lines
+            .iter()
+            .map(|(line, hits)| (*line, *hits))
+            .collect();
+        assert_eq!(lines, [(1, 1), (2, 3), (3, 0), (5, 3), (7, 1), (8, 4)]);
+        assert_eq!(missed_ranges(&coverage.files[&None].lines), "3");
+
+        let lcov = coverage.lcov();
+        assert!(lcov.starts_with(
+            "TN:\nSF:<script>\nBRDA:2,0,0,0\nBRDA:2,0,1,3\nBRF:2\nBRH:1\nDA:1,1\nDA:2,3\nDA:3,0\n"
+        ));
+        assert!(lcov.ends_with("LF:6\nLH:5\nend_of_record\n"));
+        coverage.merge_lcov(&lcov).unwrap();
+        assert_eq!(coverage.files[&None].lines[&2], 6);
+        assert_eq!(coverage.files[&None].branches[&(2, 0)], [0, 6]);
+        assert!(coverage.merge_lcov("SF:x\nDA:1\n").is_err());
+        assert!(coverage.merge_lcov("SF:x\nBRDA:1,0,x,1\n").is_err());
+
+        let summary = coverage.summary();
+        assert!(summary.contains("83.3%   50.0%   3\n"));
+    }
+
+    #[test]
+    fn branches() {
+        let code = "var n = 0;\n\
+                    while (n < 3) n = n + 1;\n\
+                    match (n) {\n\
+                    1 => print 1;\n\
+                    3 => print 3;\n\
+                    }\n\
+                    if (n > 5 or (n > 2 and true)) print n;\n\
+                    var skip = () => n > 0 and false;";
+        let mut coverage = Coverage::default();
+        cover(&mut coverage, code);
+        let lcov = coverage.lcov();
+        let records: Vec<&str> = lcov
+            .lines()
+            .filter(|record| record.starts_with("BR"))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                "BRDA:2,0,0,3",
+                "BRDA:2,0,1,1",
+                "BRDA:3,0,0,0",
+                "BRDA:3,0,1,1",
+                "BRDA:3,0,2,0",
+                "BRDA:7,0,0,1",
+                "BRDA:7,0,1,0",
+                "BRDA:7,1,0,1",
+                "BRDA:7,1,1,0",
+                "BRDA:7,2,0,1",
+                "BRDA:7,2,1,0",
+                "BRDA:8,0,0,-",
+                "BRDA:8,0,1,-",
+                "BRF:13",
+                "BRH:6",
+            ]
+        );
+
+        // a later run adds to the points read back from the report
+        let mut again = Coverage::default();
+        again.merge_lcov(&lcov).unwrap();
+        cover(&mut again, code);
+        let counts = &again.files[&None];
+        assert_eq!(counts.branches.len(), 6);
+        assert_eq!(counts.branches[&(2, 0)], [6, 2]);
+        assert_eq!(counts.branches[&(7, 2)], [2, 0]);
+        assert_eq!(counts.branches[&(8, 0)], [0, 0]);
+    }
+}
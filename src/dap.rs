@@ -0,0 +1,903 @@
+use ast::stmt::Stmt;
+use debugger::{evaluate, Lines, Mode};
+use error::{Error, ErrorKind};
+use interpreter::{Capabilities, Interpreter};
+use json::{read_message, write_message, Json};
+use module::{compile, Modules};
+use stack::{Instance, Stack};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use worker::{Hook, Worker};
+
+// Exit codes reported to the editor, as `interp` would exit with
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_INTERRUPTED: i32 = 130;
+
+/// Scripts run on a single thread.
+const THREAD_ID: u32 = 1;
+
+/// The editor at the other end of the connection.
+struct Client {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u64,
+}
+
+impl Client {
+    /// The next request, or `None` once the editor has gone away. Messages
+    /// that cannot be read are reported in the debug console and skipped.
+    fn read(&mut self) -> Option<Json> {
+        loop {
+            match read_message(&mut self.input).ok()? {
+                Some(Ok(request)) => return Some(request),
+                Some(Err(msg)) => self.output("console", &format!("{}\n", msg)),
+                None => return None,
+            }
+        }
+    }
+
+    fn send(&mut self, kind: &str, members: Vec<(&str, Json)>) {
+        self.seq += 1;
+        let mut message = vec![("seq", self.seq.into()), ("type", kind.into())];
+        message.extend(members);
+        // a closed output shows up as the end of input soon after
+        write_message(&mut self.output, &Json::object(message)).ok();
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", true.into()),
+                ("command", command(request).into()),
+                ("body", body),
+            ],
+        );
+    }
+
+    fn fail(&mut self, request: &Json, message: String) {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", false.into()),
+                ("command", command(request).into()),
+                ("message", message.into()),
+            ],
+        );
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send("event", vec![("event", event.into()), ("body", body)]);
+    }
+
+    /// Shows `text` in the editor's debug console.
+    fn output(&mut self, category: &str, text: &str) {
+        self.event(
+            "output",
+            Json::object(vec![("category", category.into()), ("output", text.into())]),
+        );
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn argument<'a>(request: &'a Json, name: &str) -> Option<&'a Json> {
+    request
+        .get("arguments")
+        .and_then(|arguments| arguments.get(name))
+}
+
+/// Sends what the script prints to the editor.
+struct ProgramOutput(Rc<RefCell<Client>>);
+
+impl Write for ProgramOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .borrow_mut()
+            .output("stdout", &String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The arguments of a `launch` request.
+struct Launch {
+    program: PathBuf,
+    args: Vec<String>,
+    stop_on_entry: bool,
+    no_debug: bool,
+}
+
+impl Launch {
+    fn parse(request: &Json) -> Result<Self, String> {
+        let program = match argument(request, "program").and_then(Json::as_str) {
+            Some(program) => PathBuf::from(program),
+            None => return Err("'launch' needs the path of a 'program'.".into()),
+        };
+        let args = argument(request, "args")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|arg| arg.as_str().map(String::from))
+            .collect();
+        let flag = |name| argument(request, name).and_then(Json::as_bool) == Some(true);
+        Ok(Self {
+            program,
+            args,
+            stop_on_entry: flag("stopOnEntry"),
+            no_debug: flag("noDebug"),
+        })
+    }
+}
+
+/// A Debug Adapter Protocol server, letting an editor run and debug one
+/// script through requests read from `input`.
+///
+/// Requests are only read while the script is paused, so breakpoints changed
+/// while it runs take effect at the next pause. A line inside a function is
+/// matched against the file that function was declared in, so breakpoints work
+/// in imported modules.
+pub struct DapServer {
+    client: Rc<RefCell<Client>>,
+    session: Session,
+    launch: Option<Launch>,
+    configured: bool,
+}
+
+impl DapServer {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        let client = Rc::new(RefCell::new(Client {
+            input,
+            output,
+            seq: 0,
+        }));
+        Self {
+            session: Session::new(client.clone()),
+            client,
+            launch: None,
+            configured: false,
+        }
+    }
+
+    /// Serves requests until the editor disconnects or closes the input.
+    pub fn run(&mut self) {
+        loop {
+            let request = match self.client.borrow_mut().read() {
+                Some(request) => request,
+                None => return,
+            };
+            match command(&request) {
+                "initialize" => {
+                    let capabilities = Json::object(vec![
+                        ("supportsConfigurationDoneRequest", true.into()),
+                        ("supportsEvaluateForHovers", true.into()),
+                        ("supportsTerminateRequest", true.into()),
+                    ]);
+                    let mut client = self.client.borrow_mut();
+                    client.respond(&request, capabilities);
+                    client.event("initialized", Json::object(vec![]));
+                }
+                "launch" => match Launch::parse(&request) {
+                    Ok(launch) => {
+                        self.launch = Some(launch);
+                        self.client.borrow_mut().respond(&request, Json::Null);
+                    }
+                    Err(msg) => self.client.borrow_mut().fail(&request, msg),
+                },
+                "configurationDone" => {
+                    self.configured = true;
+                    self.client.borrow_mut().respond(&request, Json::Null);
+                }
+                "disconnect" => {
+                    self.client.borrow_mut().respond(&request, Json::Null);
+                    return;
+                }
+                "terminate" => {
+                    let mut client = self.client.borrow_mut();
+                    client.respond(&request, Json::Null);
+                    client.event("terminated", Json::object(vec![]));
+                }
+                _ => {
+                    if !self.session.handle_common(&request) {
+                        let msg = format!("'{}' needs a paused program.", command(&request));
+                        self.client.borrow_mut().fail(&request, msg);
+                    }
+                }
+            }
+
+            if self.configured {
+                if let Some(launch) = self.launch.take() {
+                    self.run_program(launch);
+                    if self.session.disconnected {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn run_program(&mut self, launch: Launch) {
+        let code = match fs::read_to_string(&launch.program) {
+            Ok(code) => code,
+            Err(e) => {
+                let msg = format!("Cannot read '{}': {}\n", launch.program.display(), e);
+                self.client.borrow_mut().output("stderr", &msg);
+                return self.exited(EX_NOINPUT);
+            }
+        };
+        let stmts = match compile(code) {
+            Ok(stmts) => stmts,
+            Err(e) => {
+                self.report(&e);
+                return self.exited(EX_DATAERR);
+            }
+        };
+
+        let path = launch.program.canonicalize().unwrap_or(launch.program);
+        let mut stack = Interpreter::global_stack(&launch.args, Capabilities::all());
+        let mut modules = Modules::default();
+        modules.enter(path);
+        self.session.start(launch.stop_on_entry);
+        let mut output = ProgramOutput(self.client.clone());
+        let result = {
+            let mut worker = Worker::new(&mut stack)
+                .with_modules(&mut modules)
+                .with_output(&mut output);
+            if !launch.no_debug {
+                worker = worker.with_hook(&mut self.session);
+            }
+            worker.run(&stmts)
+        };
+        if self.session.disconnected {
+            return;
+        }
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) => match e.kind {
+                ErrorKind::Exit(code) => code,
+                ErrorKind::Interrupted => EX_INTERRUPTED,
+                _ => {
+                    self.report(&e);
+                    EX_SOFTWARE
+                }
+            },
+        };
+        self.exited(code);
+    }
+
+    fn report(&mut self, e: &Error) {
+        let msg = format!("[line {}] Error: {}\n", e.line, e.msg);
+        self.client.borrow_mut().output("stderr", &msg);
+    }
+
+    fn exited(&mut self, code: i32) {
+        let mut client = self.client.borrow_mut();
+        client.event("exited", Json::object(vec![("exitCode", code.into())]));
+        client.event("terminated", Json::object(vec![]));
+    }
+}
+
+/// The debugging state of the running script, consulted before each
+/// statement.
+struct Session {
+    client: Rc<RefCell<Client>>,
+    // lines to pause at, by canonical path
+    breakpoints: HashMap<PathBuf, Vec<u32>>,
+    mode: Mode,
+    // reported when `mode` makes the program pause
+    reason: &'static str,
+    lines: Lines,
+    disconnected: bool,
+}
+
+impl Session {
+    fn new(client: Rc<RefCell<Client>>) -> Self {
+        Self {
+            client,
+            breakpoints: HashMap::new(),
+            mode: Mode::Continue,
+            reason: "step",
+            lines: Lines::default(),
+            disconnected: false,
+        }
+    }
+
+    fn start(&mut self, stop_on_entry: bool) {
+        if stop_on_entry {
+            self.mode = Mode::Step;
+            self.reason = "entry";
+        } else {
+            self.mode = Mode::Continue;
+        }
+        self.lines.clear();
+    }
+
+    /// Answers the requests that do not need a paused program. Returns false
+    /// for any other request.
+    fn handle_common(&mut self, request: &Json) -> bool {
+        let body = match command(request) {
+            "setBreakpoints" => self.set_breakpoints(request),
+            "setExceptionBreakpoints" => Json::object(vec![]),
+            "threads" => {
+                let thread = Json::object(vec![("id", THREAD_ID.into()), ("name", "main".into())]);
+                Json::object(vec![("threads", vec![thread].into())])
+            }
+            _ => return false,
+        };
+        self.client.borrow_mut().respond(request, body);
+        true
+    }
+
+    fn set_breakpoints(&mut self, request: &Json) -> Json {
+        let path = argument(request, "source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        let path = path.canonicalize().unwrap_or(path);
+        let lines: Vec<u32> = argument(request, "breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_u64))
+            .map(|line| line as u32)
+            .collect();
+        let verified = lines
+            .iter()
+            .map(|&line| Json::object(vec![("verified", true.into()), ("line", line.into())]))
+            .collect::<Vec<Json>>();
+        self.breakpoints.insert(path, lines);
+        Json::object(vec![("breakpoints", verified.into())])
+    }
+
+    fn is_breakpoint(&self, file: Option<&Path>, line: u32) -> bool {
+        file.and_then(|file| self.breakpoints.get(file))
+            .is_some_and(|lines| lines.contains(&line))
+    }
+
+    /// Reports the pause and answers requests until one resumes the program.
+    fn pause(&mut self, worker: &mut Worker, reason: &str, line: u32) -> Result<(), Error> {
+        self.client.borrow_mut().event(
+            "stopped",
+            Json::object(vec![
+                ("reason", reason.into()),
+                ("threadId", THREAD_ID.into()),
+                ("allThreadsStopped", true.into()),
+            ]),
+        );
+        loop {
+            let request = self.client.borrow_mut().read();
+            let request = match request {
+                Some(request) => request,
+                None => {
+                    self.disconnected = true;
+                    return Err(Error::interrupted(line));
+                }
+            };
+            let depth = worker.call_depth();
+            let mode = match command(&request) {
+                "continue" => Mode::Continue,
+                "next" => Mode::Next(depth),
+                "stepIn" => Mode::Step,
+                "stepOut" => Mode::Finish(depth),
+                "disconnect" | "terminate" => {
+                    self.disconnected = command(&request) == "disconnect";
+                    self.client.borrow_mut().respond(&request, Json::Null);
+                    return Err(Error::interrupted(line));
+                }
+                "stackTrace" => {
+                    let body = Self::stack_trace(worker, line);
+                    self.client.borrow_mut().respond(&request, body);
+                    continue;
+                }
+                "scopes" => {
+                    let frame = argument(&request, "frameId").and_then(Json::as_u64);
+                    match frame.and_then(|frame| Self::scopes(worker, frame as usize)) {
+                        Some(body) => self.client.borrow_mut().respond(&request, body),
+                        None => self
+                            .client
+                            .borrow_mut()
+                            .fail(&request, "Unknown stack frame.".into()),
+                    }
+                    continue;
+                }
+                "variables" => {
+                    let reference = argument(&request, "variablesReference")
+                        .and_then(Json::as_u64)
+                        .unwrap_or(0);
+                    let body = Self::variables(worker.stack, reference as usize);
+                    self.client.borrow_mut().respond(&request, body);
+                    continue;
+                }
+                "evaluate" => {
+                    let expression = argument(&request, "expression")
+                        .and_then(Json::as_str)
+                        .unwrap_or("");
+                    // the client must not be borrowed while the script runs
+                    let result = evaluate(worker, expression);
+                    let mut client = self.client.borrow_mut();
+                    match result {
+                        Ok(value) => client.respond(
+                            &request,
+                            Json::object(vec![
+                                ("result", show(&value).into()),
+                                ("type", value.type_name().into()),
+                                ("variablesReference", 0u32.into()),
+                            ]),
+                        ),
+                        Err(msg) => client.fail(&request, msg),
+                    }
+                    continue;
+                }
+                _ => {
+                    if !self.handle_common(&request) {
+                        let msg = format!("Unsupported request '{}'.", command(&request));
+                        self.client.borrow_mut().fail(&request, msg);
+                    }
+                    continue;
+                }
+            };
+            let body = match mode {
+                Mode::Continue => Json::object(vec![("allThreadsContinued", true.into())]),
+                _ => Json::Null,
+            };
+            self.mode = mode;
+            self.reason = "step";
+            self.client.borrow_mut().respond(&request, body);
+            return Ok(());
+        }
+    }
+
+    /// One frame for the script and one per call in progress, innermost first.
+    /// The ids count up from the script's frame. A call's frame is shown in
+    /// the file its function was declared in.
+    fn stack_trace(worker: &Worker, line: u32) -> Json {
+        let source = |file: Option<&Path>| match file {
+            Some(file) => Json::object(vec![
+                (
+                    "name",
+                    file.file_name()
+                        .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+                        .into(),
+                ),
+                ("path", file.display().to_string().into()),
+            ]),
+            None => Json::Null,
+        };
+        let calls = worker.calls();
+        let frames: Vec<Json> = (0..=calls.len())
+            .rev()
+            .map(|id| {
+                let (name, file) = match id {
+                    0 => ("<main>", worker.module_file()),
+                    id => (calls[id - 1].name.as_str(), calls[id - 1].file.as_deref()),
+                };
+                // a caller is paused on the line of its call
+                let line = calls.get(id).map_or(line, |call| call.line);
+                Json::object(vec![
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("source", source(file)),
+                    ("line", line.into()),
+                    ("column", 1u32.into()),
+                ])
+            })
+            .collect();
+        Json::object(vec![
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", frames.into()),
+        ])
+    }
+
+    /// The scopes of frame `frame`, innermost first. A scope's variables
+    /// reference is its height in the stack.
+    fn scopes(worker: &Worker, frame: usize) -> Option<Json> {
+        let calls = worker.calls();
+        if frame > calls.len() {
+            return None;
+        }
+        let low = match frame {
+            0 => 1,
+            frame => calls[frame - 1].height,
+        };
+        let high = calls
+            .get(frame)
+            .map_or(worker.stack.height, |call| call.height - 1);
+        let scopes: Vec<Json> = (low..=high)
+            .rev()
+            .map(|height| {
+                let name = if height == 1 {
+                    "Globals"
+                } else if frame > 0 && height == low {
+                    "Arguments"
+                } else if height == high {
+                    "Locals"
+                } else {
+                    "Block"
+                };
+                Json::object(vec![
+                    ("name", name.into()),
+                    ("variablesReference", height.into()),
+                    ("expensive", false.into()),
+                ])
+            })
+            .collect();
+        Some(Json::object(vec![("scopes", scopes.into())]))
+    }
+
+    fn variables(stack: &Stack, height: usize) -> Json {
+        let mut scope = Some(stack);
+        let mut variables = Vec::new();
+        while let Some(stack) = scope {
+            if stack.height == height {
+                for (name, value) in stack.bindings() {
+                    variables.push(Json::object(vec![
+                        ("name", name.into()),
+                        ("value", show(value).into()),
+                        ("type", value.type_name().into()),
+                        ("variablesReference", 0u32.into()),
+                    ]));
+                }
+                break;
+            }
+            scope = stack.parent();
+        }
+        Json::object(vec![("variables", variables.into())])
+    }
+}
+
+/// A value as shown in the editor, with strings quoted.
+fn show(value: &Instance) -> String {
+    match value {
+        Instance::String(s) => format!("{:?}", s),
+        value => value.to_string(),
+    }
+}
+
+impl Hook for Session {
+    fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error> {
+        let line = match stmt {
+            Stmt::Block { .. } | Stmt::Empty => return Ok(()),
+            _ => match stmt.line() {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+        };
+        let arrived = self.lines.arrive(worker, stmt, line);
+        let file = worker.current_file().map(Path::to_path_buf);
+
+        if self.mode.pauses_at(worker.call_depth()) {
+            let reason = self.reason;
+            self.pause(worker, reason, line)
+        } else if arrived && self.is_breakpoint(file.as_deref(), line) {
+            self.pause(worker, "breakpoint", line)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use testing::SharedOutput;
+
+    /// Runs a server on the requests `(command, arguments)` and returns what
+    /// it sent back.
+    fn exchange(requests: &[(&str, Json)]) -> Vec<Json> {
+        exchange_after(Vec::new(), requests)
+    }
+
+    /// Like `exchange`, with the raw bytes `input` sent before the requests.
+    fn exchange_after(mut input: Vec<u8>, requests: &[(&str, Json)]) -> Vec<Json> {
+        for (i, (command, arguments)) in requests.iter().enumerate() {
+            let request = Json::object(vec![
+                ("seq", (i + 1).into()),
+                ("type", "request".into()),
+                ("command", (*command).into()),
+                ("arguments", arguments.clone()),
+            ]);
+            write_message(&mut input, &request).unwrap();
+        }
+        let output = SharedOutput::default();
+        DapServer::new(Box::new(Cursor::new(input)), Box::new(output.clone())).run();
+
+        let mut reader = Cursor::new(output.take().into_bytes());
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message.unwrap());
+        }
+        messages
+    }
+
+    /// A short description of a message: the command of a response, or the
+    /// event with its most telling detail.
+    fn describe(message: &Json) -> String {
+        let text = |value: Option<&Json>| value.map_or(String::new(), |value| value.to_string());
+        let body = message.get("body");
+        match message.get("type").and_then(Json::as_str) {
+            Some("response") => format!("{} {}", command(message), text(message.get("success"))),
+            _ => {
+                let detail = body.and_then(|body| {
+                    body.get("reason")
+                        .or_else(|| body.get("output"))
+                        .or_else(|| body.get("exitCode"))
+                });
+                let event = message.get("event").and_then(Json::as_str).unwrap_or("");
+                format!("{} {}", event, text(detail))
+            }
+        }
+        .trim()
+        .to_string()
+    }
+
+    #[test]
+    fn debug_session() {
+        let file = env::temp_dir().join("wendell_dap_test.aul");
+        fs::write(
+            &file,
+            "var a = 1;\n\
+             fun f(x) {\n\
+             var y = x * 2;\n\
+             print y;\n\
+             return y;\n\
+             }\n\
+             var b = f(a);\n\
+             print b + 1;\n\
+             while (b < 4) {\n\
+             b = b + 1;\n\
+             }",
+        )
+        .unwrap();
+        let path = file.display().to_string();
+        let source = Json::object(vec![("path", path.clone().into())]);
+        let no_arguments = Json::object(vec![]);
+        let messages = exchange(&[
+            ("initialize", no_arguments.clone()),
+            ("launch", Json::object(vec![("program", path.into())])),
+            (
+                "setBreakpoints",
+                Json::object(vec![
+                    ("source", source),
+                    (
+                        "breakpoints",
+                        vec![
+                            Json::object(vec![("line", 4u32.into())]),
+                            Json::object(vec![("line", 10u32.into())]),
+                        ]
+                        .into(),
+                    ),
+                ]),
+            ),
+            ("configurationDone", no_arguments.clone()),
+            ("threads", no_arguments.clone()),
+            ("stackTrace", Json::object(vec![("threadId", 1u32.into())])),
+            ("scopes", Json::object(vec![("frameId", 1u32.into())])),
+            (
+                "variables",
+                Json::object(vec![("variablesReference", 3u32.into())]),
+            ),
+            (
+                "evaluate",
+                Json::object(vec![("expression", "x + y".into())]),
+            ),
+            ("evaluate", Json::object(vec![("expression", "z".into())])),
+            ("next", Json::object(vec![("threadId", 1u32.into())])),
+            ("stackTrace", Json::object(vec![("threadId", 1u32.into())])),
+            ("stepOut", Json::object(vec![("threadId", 1u32.into())])),
+            ("stackTrace", Json::object(vec![("threadId", 1u32.into())])),
+            ("continue", Json::object(vec![("threadId", 1u32.into())])),
+            ("evaluate", Json::object(vec![("expression", "b".into())])),
+            ("continue", Json::object(vec![("threadId", 1u32.into())])),
+            ("evaluate", Json::object(vec![("expression", "b".into())])),
+            ("continue", Json::object(vec![("threadId", 1u32.into())])),
+            ("disconnect", no_arguments),
+        ]);
+
+        let transcript: Vec<String> = messages.iter().map(describe).collect();
+        assert_eq!(
+            transcript,
+            [
+                "initialize true",
+                "initialized",
+                "launch true",
+                "setBreakpoints true",
+                "configurationDone true",
+                "stopped \"breakpoint\"",
+                "threads true",
+                "stackTrace true",
+                "scopes true",
+                "variables true",
+                "evaluate true",
+                "evaluate false",
+                "next true",
+                "output \"2\\n\"",
+                "stopped \"step\"",
+                "stackTrace true",
+                "stepOut true",
+                "stopped \"step\"",
+                "stackTrace true",
+                "continue true",
+                "output \"3\\n\"",
+                "stopped \"breakpoint\"",
+                "evaluate true",
+                "continue true",
+                "stopped \"breakpoint\"",
+                "evaluate true",
+                "continue true",
+                "exited 0",
+                "terminated",
+                "disconnect true",
+            ]
+        );
+
+        let body = |command: &str| {
+            messages
+                .iter()
+                .find(|message| message.get("command").and_then(Json::as_str) == Some(command))
+                .and_then(|message| message.get("body"))
+                .unwrap()
+                .to_string()
+        };
+        let stack_trace = body("stackTrace");
+        assert!(stack_trace.contains(r#""id":1,"name":"f","#));
+        assert!(stack_trace.contains(r#""id":0,"name":"<main>","#));
+        assert!(stack_trace.contains(r#""line":4,"#));
+        assert!(stack_trace.contains(r#""line":7,"#));
+        assert_eq!(
+            body("scopes"),
+            r#"{"scopes":[{"name":"Locals","variablesReference":3,"expensive":false},{"name":"Arguments","variablesReference":2,"expensive":false}]}"#
+        );
+        assert_eq!(
+            body("variables"),
+            r#"{"variables":[{"name":"y","value":"2","type":"Number","variablesReference":0}]}"#
+        );
+        assert!(body("evaluate").starts_with(r#"{"result":"3","type":"Number""#));
+
+        // where each stop was, from the top frame of the stack traces
+        let lines: Vec<String> = messages
+            .iter()
+            .filter(|message| message.get("command").and_then(Json::as_str) == Some("stackTrace"))
+            .map(|message| {
+                let frames = message.get("body").unwrap().get("stackFrames").unwrap();
+                frames.as_array().unwrap()[0]
+                    .get("line")
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(lines, ["4", "5", "8"]);
+
+        // the breakpoint in the loop body pauses on every iteration
+        let results: Vec<String> = messages
+            .iter()
+            .filter(|message| message.get("command").and_then(Json::as_str) == Some("evaluate"))
+            .filter_map(|message| {
+                message
+                    .get("body")?
+                    .get("result")?
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect();
+        assert_eq!(results, ["3", "2", "3"]);
+    }
+
+    #[test]
+    fn invalid_message() {
+        let input = b"Content-Length: 2\r\n\r\n{]".to_vec();
+        let messages = exchange_after(input, &[("disconnect", Json::object(vec![]))]);
+        let transcript: Vec<String> = messages.iter().map(describe).collect();
+        assert_eq!(transcript.len(), 2);
+        assert!(transcript[0].starts_with("output \"Invalid JSON"));
+        assert_eq!(transcript[1], "disconnect true");
+    }
+
+    #[test]
+    fn imported_frames() {
+        let dir = env::temp_dir().join("wendell_dap_import_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("util.aul"),
+            "fun twice(x) {\n\
+             var y = x * 2;\n\
+             return y;\n\
+             }",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.aul"),
+            "import \"util.aul\";\n\
+             var a = twice(3);\n\
+             print a;",
+        )
+        .unwrap();
+        let main = dir.join("main.aul").canonicalize().unwrap();
+        let util = dir.join("util.aul").canonicalize().unwrap();
+        let no_arguments = Json::object(vec![]);
+        let messages = exchange(&[
+            ("initialize", no_arguments.clone()),
+            (
+                "launch",
+                Json::object(vec![("program", main.display().to_string().into())]),
+            ),
+            (
+                "setBreakpoints",
+                Json::object(vec![
+                    (
+                        "source",
+                        Json::object(vec![("path", util.display().to_string().into())]),
+                    ),
+                    (
+                        "breakpoints",
+                        vec![Json::object(vec![("line", 2u32.into())])].into(),
+                    ),
+                ]),
+            ),
+            ("configurationDone", no_arguments.clone()),
+            ("stackTrace", Json::object(vec![("threadId", 1u32.into())])),
+            ("continue", Json::object(vec![("threadId", 1u32.into())])),
+            ("disconnect", no_arguments),
+        ]);
+
+        let transcript: Vec<String> = messages.iter().map(describe).collect();
+        assert!(transcript.contains(&"stopped \"breakpoint\"".to_string()));
+        assert!(transcript.contains(&"output \"6\\n\"".to_string()));
+
+        let stack_trace = messages
+            .iter()
+            .find(|message| message.get("command").and_then(Json::as_str) == Some("stackTrace"))
+            .and_then(|message| message.get("body"))
+            .and_then(|body| body.get("stackFrames"))
+            .unwrap();
+        let frames: Vec<(String, String, String)> = stack_trace
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                let path = frame.get("source").and_then(|source| source.get("path"));
+                (
+                    frame.get("name").unwrap().to_string(),
+                    path.and_then(Json::as_str).unwrap_or("").to_string(),
+                    frame.get("line").unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                (
+                    "\"twice\"".to_string(),
+                    util.display().to_string(),
+                    "2".to_string()
+                ),
+                (
+                    "\"<main>\"".to_string(),
+                    main.display().to_string(),
+                    "2".to_string()
+                ),
+            ]
+        );
+    }
+}
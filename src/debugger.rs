@@ -0,0 +1,505 @@
+use ast::stmt::Stmt;
+use error::Error;
+use parser::Parser;
+use scanner::Scanner;
+use stack::{Instance, Stack};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use worker::{Hook, Worker};
+
+const DEBUG_HELP: &str = "\
+step, s            Run to the next statement
+next, n            Run to the next statement, stepping over calls
+finish, f          Run until the current function returns
+continue, c        Run until a breakpoint
+break, b <line>    Pause at <line>; use <file>:<line> for an imported file
+delete, d <line>   Remove the breakpoint at <line>
+locals, l          List the variables of the current scope and its parents
+print, p <expr>    Evaluate <expr> where the program is paused
+watch, w <expr>    Print <expr> every time the program pauses
+unwatch <n>        Remove the n-th watch expression
+help, h            Show this help
+quit, q            Stop the program";
+
+/// When the debugger takes control again.
+pub enum Mode {
+    Step,
+    /// Next statement at this call depth or above.
+    Next(usize),
+    /// Next statement above this call depth.
+    Finish(usize),
+    Continue,
+}
+
+impl Mode {
+    /// Whether to pause before a statement run at call depth `depth`.
+    pub fn pauses_at(&self, depth: usize) -> bool {
+        match *self {
+            Mode::Step => true,
+            Mode::Next(start) => depth <= start,
+            Mode::Finish(start) => depth < start,
+            Mode::Continue => false,
+        }
+    }
+}
+
+/// Tells apart the statements that arrive on a line from the others run on
+/// it before the program leaves it, so a breakpoint pauses once per visit of
+/// its line. Running the first statement of the line again, as in the next
+/// iteration of a loop, is a new visit.
+#[derive(Default)]
+pub struct Lines {
+    // file and line of the last visit, with the address of its first statement
+    last: Option<(Option<PathBuf>, u32, usize)>,
+}
+
+impl Lines {
+    /// Records `stmt`, on `line` of the running file. Returns whether it
+    /// starts a new visit of that line.
+    pub fn arrive(&mut self, worker: &Worker, stmt: &Stmt, line: u32) -> bool {
+        let file = worker.current_file().map(Path::to_path_buf);
+        let address = stmt as *const Stmt as usize;
+        if let Some((ref last_file, last_line, first)) = self.last {
+            if *last_file == file && last_line == line && first != address {
+                return false;
+            }
+        }
+        self.last = Some((file, line, address));
+        true
+    }
+
+    /// Forgets the last visit, for a program started again.
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+}
+
+/// A line to pause at. Without a file it refers to the main script.
+#[derive(PartialEq)]
+struct Breakpoint {
+    file: Option<String>,
+    line: u32,
+}
+
+/// An interactive step debugger, reading commands from `input` whenever the
+/// program pauses.
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    mode: Mode,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<String>,
+    // the main script, as lines starting at `main_start`
+    main_file: Option<PathBuf>,
+    main_lines: Vec<String>,
+    main_start: u32,
+    // imported files, read when the program first pauses in them
+    files: HashMap<PathBuf, Vec<String>>,
+    lines: Lines,
+    last_command: String,
+}
+
+impl Debugger {
+    /// A debugger that pauses before the first statement.
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            input,
+            output,
+            mode: Mode::Step,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            main_file: None,
+            main_lines: Vec::new(),
+            main_start: 1,
+            files: HashMap::new(),
+            lines: Lines::default(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Sets the code of the main script, used to show where the program is.
+    /// `file` is where it was read from, if anywhere.
+    pub fn set_source(&mut self, file: Option<PathBuf>, code: &str, start_line: u32) {
+        self.main_file = file;
+        self.main_lines = code.lines().map(String::from).collect();
+        self.main_start = start_line;
+        self.lines.clear();
+    }
+
+    fn is_main(&self, file: Option<&Path>) -> bool {
+        file.is_none() || file == self.main_file.as_deref()
+    }
+
+    fn is_breakpoint(&self, file: Option<&Path>, line: u32) -> bool {
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.line == line
+                && match (&breakpoint.file, file) {
+                    (None, _) => self.is_main(file),
+                    (Some(name), Some(file)) => file.ends_with(name),
+                    (Some(_), None) => false,
+                }
+        })
+    }
+
+    fn source_line(&mut self, file: Option<&Path>, line: u32) -> String {
+        let main = self.is_main(file);
+        let lines = match file {
+            Some(file) if !main => self.files.entry(file.to_path_buf()).or_insert_with(|| {
+                fs::read_to_string(file)
+                    .map(|code| code.lines().map(String::from).collect())
+                    .unwrap_or_default()
+            }),
+            _ => &self.main_lines,
+        };
+        let start = if main { self.main_start } else { 1 };
+        line.checked_sub(start)
+            .and_then(|i| lines.get(i as usize))
+            .map_or(String::new(), |text| text.trim().to_string())
+    }
+
+    fn pause(&mut self, worker: &mut Worker, file: Option<&Path>, line: u32) -> Result<(), Error> {
+        let text = self.source_line(file, line);
+        match file {
+            Some(file) if !self.is_main(Some(file)) => {
+                writeln!(self.output, "{}:{}: {}", file.display(), line, text)
+            }
+            _ => writeln!(self.output, "line {}: {}", line, text),
+        }
+        .ok();
+        for (i, watch) in self.watches.iter().enumerate() {
+            let value = Self::eval(worker, watch);
+            writeln!(self.output, "  watch {}: {} = {}", i + 1, watch, value).ok();
+        }
+
+        loop {
+            write!(self.output, "(debug) ").ok();
+            self.output.flush().ok();
+            let mut command = String::new();
+            match self.input.read_line(&mut command) {
+                Ok(0) | Err(_) => {
+                    // no one is left to answer, so let the program finish
+                    self.breakpoints.clear();
+                    self.mode = Mode::Continue;
+                    return Ok(());
+                }
+                Ok(_) => (),
+            }
+            let mut command = command.trim().to_string();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+            if self.run_command(worker, &command, line)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs a debugger command. Returns true when the program should resume.
+    fn run_command(
+        &mut self,
+        worker: &mut Worker,
+        command: &str,
+        line: u32,
+    ) -> Result<bool, Error> {
+        let (name, arg) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        let depth = worker.call_depth();
+        match (name, arg) {
+            ("step", "") | ("s", "") => self.mode = Mode::Step,
+            ("next", "") | ("n", "") => self.mode = Mode::Next(depth),
+            ("finish", "") | ("f", "") => self.mode = Mode::Finish(depth),
+            ("continue", "") | ("c", "") => self.mode = Mode::Continue,
+            ("quit", "") | ("q", "") => return Err(Error::interrupted(line)),
+            ("break", location) | ("b", location) if !location.is_empty() => {
+                match Self::parse_breakpoint(location) {
+                    Some(breakpoint) => {
+                        if !self.breakpoints.contains(&breakpoint) {
+                            self.breakpoints.push(breakpoint);
+                        }
+                    }
+                    None => self.say(&format!("Invalid breakpoint '{}'.", location)),
+                }
+                return Ok(false);
+            }
+            ("delete", location) | ("d", location) if !location.is_empty() => {
+                match Self::parse_breakpoint(location) {
+                    Some(breakpoint) => self.breakpoints.retain(|b| *b != breakpoint),
+                    None => self.say(&format!("Invalid breakpoint '{}'.", location)),
+                }
+                return Ok(false);
+            }
+            ("help", "") | ("h", "") => {
+                self.say(DEBUG_HELP);
+                return Ok(false);
+            }
+            ("locals", "") | ("l", "") => {
+                self.print_locals(worker.stack);
+                return Ok(false);
+            }
+            ("print", expr) | ("p", expr) if !expr.is_empty() => {
+                let value = Self::eval(worker, expr);
+                self.say(&value);
+                return Ok(false);
+            }
+            ("watch", expr) | ("w", expr) if !expr.is_empty() => {
+                self.watches.push(expr.to_string());
+                let value = Self::eval(worker, expr);
+                self.say(&format!(
+                    "  watch {}: {} = {}",
+                    self.watches.len(),
+                    expr,
+                    value
+                ));
+                return Ok(false);
+            }
+            ("unwatch", n) => {
+                match n.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= self.watches.len() => {
+                        self.watches.remove(n - 1);
+                    }
+                    _ => self.say(&format!("No watch expression '{}'.", n)),
+                }
+                return Ok(false);
+            }
+            _ => {
+                self.say(&format!(
+                    "Unknown command '{}'. Commands:\n{}",
+                    command, DEBUG_HELP
+                ));
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn parse_breakpoint(location: &str) -> Option<Breakpoint> {
+        let (file, line) = match location.rfind(':') {
+            Some(i) => (Some(location[..i].to_string()), &location[i + 1..]),
+            None => (None, location),
+        };
+        let line = line.parse().ok()?;
+        Some(Breakpoint { file, line })
+    }
+
+    fn print_locals(&mut self, stack: &Stack) {
+        let mut scope = Some(stack);
+        while let Some(stack) = scope {
+            // builtins live at the bottom and are not worth listing
+            if stack.height == 0 {
+                break;
+            }
+            let label = if stack.height == 1 {
+                "global".to_string()
+            } else {
+                format!("scope {}", stack.height)
+            };
+            for (name, value) in stack.bindings() {
+                let text = match value {
+                    Instance::String(s) => format!("{:?}", s),
+                    value => value.to_string(),
+                };
+                writeln!(self.output, "[{}] {} = {}", label, name, text).ok();
+            }
+            scope = stack.parent();
+        }
+    }
+
+    /// Evaluates an expression typed by the user, describing any error.
+    fn eval(worker: &mut Worker, code: &str) -> String {
+        match evaluate(worker, code) {
+            Ok(value) => value.to_string(),
+            Err(msg) => format!("error: {}", msg),
+        }
+    }
+
+    fn say(&mut self, text: &str) {
+        writeln!(self.output, "{}", text).ok();
+    }
+}
+
+/// Evaluates the expression `code` in the scope the program is paused in.
+pub fn evaluate(worker: &mut Worker, code: &str) -> Result<Instance, String> {
+    let mut scanner = Scanner::new(code.to_string(), 1);
+    scanner.scan_all_tokens().map_err(|e| e.msg)?;
+    let mut parser = Parser::new_repl(scanner.tokens);
+    parser.parse().map_err(|e| e.msg)?;
+    match parser.stmts.as_slice() {
+        [Stmt::Expression { expression }] => worker.evaluate(expression).map_err(|e| e.msg),
+        _ => Err("expected an expression".to_string()),
+    }
+}
+
+impl Hook for Debugger {
+    fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error> {
+        // blocks are paused on at their first statement
+        let line = match stmt {
+            Stmt::Block { .. } | Stmt::Empty => return Ok(()),
+            _ => match stmt.line() {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+        };
+        let arrived = self.lines.arrive(worker, stmt, line);
+        let file = worker.current_file().map(Path::to_path_buf);
+        let file = file.as_deref();
+
+        let pause = self.mode.pauses_at(worker.call_depth());
+        if pause || (arrived && self.is_breakpoint(file, line)) {
+            self.pause(worker, file, line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use module::{compile, Modules};
+    use std::env;
+    use std::io::Cursor;
+    use testing::SharedOutput;
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let code = "var a = 1;\n\
+                    fun f(x) {\n\
+                    var y = x * 2;\n\
+                    return y;\n\
+                    }\n\
+                    var b = f(a);\n\
+                    b = b + 1;";
+        let commands = "b 4\nw a + 1\nc\nl\np y * 10\nf\nn\n\n";
+        let output = SharedOutput::default();
+        let mut debugger = Debugger::new(
+            Box::new(Cursor::new(commands.as_bytes().to_vec())),
+            Box::new(output.clone()),
+        );
+        debugger.set_source(None, code, 1);
+        let mut stack = Stack::default();
+        stack.push();
+        Worker::new(&mut stack)
+            .with_hook(&mut debugger)
+            .run(&compile(code.into()).unwrap())
+            .unwrap();
+
+        let output = output.text();
+        let pauses: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("line "))
+            .map(|line| line.trim_start_matches("(debug) "))
+            .collect();
+        assert_eq!(
+            pauses,
+            [
+                "line 1: var a = 1;",
+                "line 4: return y;",
+                "line 7: b = b + 1;",
+            ]
+        );
+        assert!(output.contains("watch 1: a + 1 = 2"));
+        assert!(output.contains("[scope 3] y = 2"));
+        assert!(output.contains("[scope 2] x = 1"));
+        assert!(output.contains("[global] a = 1"));
+        assert!(output.contains("(debug) 20\n"));
+    }
+
+    #[test]
+    fn breakpoint_in_loop() {
+        let code = "var i = 0;\n\
+                    while (i < 3) {\n\
+                    i = i + 1; if (i > 0) i = i + 0;\n\
+                    }\n\
+                    fun f() { return i; }\n\
+                    f();";
+        let commands = "b 3\nb 6\nc\np i\nc\np i\nc\np i\nc\nc\n";
+        let output = SharedOutput::default();
+        let mut debugger = Debugger::new(
+            Box::new(Cursor::new(commands.as_bytes().to_vec())),
+            Box::new(output.clone()),
+        );
+        debugger.set_source(None, code, 1);
+        let mut stack = Stack::default();
+        stack.push();
+        // the interpreter runs a script this way, for the value of `f();`
+        Worker::new(&mut stack)
+            .with_hook(&mut debugger)
+            .run_last_value(&compile(code.into()).unwrap())
+            .unwrap();
+
+        let output = output.text();
+        let pauses: Vec<&str> = output
+            .lines()
+            .map(|line| line.trim_start_matches("(debug) "))
+            .filter(|line| line.starts_with("line ") || line.parse::<f64>().is_ok())
+            .collect();
+        assert_eq!(
+            pauses,
+            [
+                "line 1: var i = 0;",
+                "line 3: i = i + 1; if (i > 0) i = i + 0;",
+                "0",
+                "line 3: i = i + 1; if (i > 0) i = i + 0;",
+                "1",
+                "line 3: i = i + 1; if (i > 0) i = i + 0;",
+                "2",
+                "line 6: f();",
+            ]
+        );
+    }
+
+    #[test]
+    fn breakpoint_in_imported_function() {
+        let dir = env::temp_dir().join("wendell_debugger_import_test");
+        fs::create_dir_all(&dir).unwrap();
+        let util = "fun twice(x) {\n  var y = x * 2;\n  return y;\n}";
+        fs::write(dir.join("util.aul"), util).unwrap();
+        let main = dir.join("main.aul");
+        let code = "import \"util.aul\";\nvar a = twice(3);\nvar b = 2;";
+        fs::write(&main, code).unwrap();
+        let main = main.canonicalize().unwrap();
+
+        // line 2 of the main script must not pause inside util.aul
+        let commands = "b util.aul:2\nb 3\nc\nl\nc\nc\n";
+        let output = SharedOutput::default();
+        let mut debugger = Debugger::new(
+            Box::new(Cursor::new(commands.as_bytes().to_vec())),
+            Box::new(output.clone()),
+        );
+        debugger.set_source(Some(main.clone()), code, 1);
+        let mut modules = Modules::default();
+        modules.enter(main);
+        let mut stack = Stack::default();
+        stack.push();
+        Worker::new(&mut stack)
+            .with_modules(&mut modules)
+            .with_hook(&mut debugger)
+            .run(&compile(code.into()).unwrap())
+            .unwrap();
+
+        let output = output.text();
+        let pauses: Vec<&str> = output
+            .lines()
+            .map(|line| line.trim_start_matches("(debug) "))
+            .filter(|line| line.contains(": "))
+            .filter(|line| !line.starts_with('['))
+            .collect();
+        let util_line = format!(
+            "{}:2: var y = x * 2;",
+            dir.join("util.aul").canonicalize().unwrap().display()
+        );
+        assert_eq!(
+            pauses,
+            [
+                "line 1: import \"util.aul\";",
+                util_line.as_str(),
+                "line 3: var b = 2;"
+            ]
+        );
+        assert!(output.contains("[scope 2] x = 3"));
+    }
+}
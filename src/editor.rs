@@ -0,0 +1,481 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem::take;
+use std::path::PathBuf;
+
+const HISTORY_FILE: &str = ".wendell_history";
+const HISTORY_SIZE: usize = 1000;
+
+/// A minimal line editor for the REPL. On a Linux terminal it switches to raw
+/// mode for cursor movement, history and tab completion; anywhere else it
+/// reads plain lines from stdin.
+pub struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    interactive: bool,
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    EndOfFile,
+    Unknown,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let mut history = Vec::new();
+        if let Some(file) = history_path.as_ref().and_then(|path| File::open(path).ok()) {
+            history = BufReader::new(file).lines().map_while(Result::ok).collect();
+            let skip = history.len().saturating_sub(HISTORY_SIZE);
+            history.drain(..skip);
+        }
+        Self {
+            history,
+            history_path,
+            interactive: false,
+        }
+    }
+
+    /// Reads one line, or `None` at the end of input. `complete` maps the word
+    /// before the cursor to the candidates that could replace it.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let raw_mode = raw::RawMode::enable();
+        self.interactive = raw_mode.is_some();
+        if self.interactive {
+            self.edit_line(prompt, complete)
+        } else {
+            Self::plain_line(prompt)
+        }
+    }
+
+    /// Remembers a line typed at a terminal in memory and in the history file.
+    pub fn add_history(&mut self, line: &str) {
+        if !self.interactive
+            || line.trim().is_empty()
+            || self.history.last().map(String::as_str) == Some(line)
+        {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        if let Some(ref path) = self.history_path {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                writeln!(file, "{}", line).ok();
+            }
+        }
+    }
+
+    fn plain_line(prompt: &str) -> io::Result<Option<String>> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        while line.ends_with('\n') || line.ends_with('\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    fn edit_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Option<String>> {
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // index into history while browsing it, and the line being edited before
+        let mut browsing = self.history.len();
+        let mut edited: Vec<char> = Vec::new();
+
+        Self::refresh(prompt, &line, cursor)?;
+        loop {
+            match Self::read_key()? {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(Some(line.into_iter().collect()));
+                }
+                Key::Tab => {
+                    if let Some(listing) = Self::complete(&mut line, &mut cursor, complete) {
+                        print!("\r\n{}\r\n", listing);
+                    }
+                }
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left if cursor > 0 => cursor -= 1,
+                Key::Right if cursor < line.len() => cursor += 1,
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::Up if browsing > 0 => {
+                    if browsing == self.history.len() {
+                        edited = take(&mut line);
+                    }
+                    browsing -= 1;
+                    line = self.history[browsing].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if browsing < self.history.len() => {
+                    browsing += 1;
+                    line = match self.history.get(browsing) {
+                        Some(entry) => entry.chars().collect(),
+                        None => take(&mut edited),
+                    };
+                    cursor = line.len();
+                }
+                Key::KillToEnd => line.truncate(cursor),
+                Key::KillToStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Interrupt => {
+                    print!("^C\r\n");
+                    line.clear();
+                    cursor = 0;
+                    browsing = self.history.len();
+                }
+                Key::EndOfFile if line.is_empty() => {
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(None);
+                }
+                Key::EndOfFile if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                _ => (),
+            }
+            Self::refresh(prompt, &line, cursor)?;
+        }
+    }
+
+    /// Completes the word before the cursor. Returns the candidates to list
+    /// when the completion is ambiguous.
+    fn complete(
+        line: &mut Vec<char>,
+        cursor: &mut usize,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> Option<String> {
+        let start = line[..*cursor]
+            .iter()
+            .rposition(|c| !(c.is_ascii_alphanumeric() || *c == '_'))
+            .map_or(0, |i| i + 1);
+        let prefix: String = line[start..*cursor].iter().collect();
+        if prefix.is_empty() {
+            line.splice(*cursor..*cursor, "    ".chars());
+            *cursor += 4;
+            return None;
+        }
+
+        let mut candidates = complete(&prefix);
+        candidates.sort();
+        candidates.dedup();
+
+        let first = candidates.first()?;
+        let common = candidates
+            .iter()
+            .fold(first.chars().count(), |len, candidate| {
+                first
+                    .chars()
+                    .zip(candidate.chars())
+                    .take(len)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            });
+        let mut insert: Vec<char> = first
+            .chars()
+            .take(common)
+            .skip(prefix.chars().count())
+            .collect();
+        if candidates.len() == 1 {
+            insert.push(' ');
+        }
+
+        if insert.is_empty() {
+            return Some(candidates.join("  "));
+        }
+        let inserted = insert.len();
+        line.splice(*cursor..*cursor, insert);
+        *cursor += inserted;
+        None
+    }
+
+    fn refresh(prompt: &str, line: &[char], cursor: usize) -> io::Result<()> {
+        let text: String = line.iter().collect();
+        let column = prompt.chars().count() + cursor;
+        let mut stdout = io::stdout();
+        write!(stdout, "\r{}{}\x1b[K\r", prompt, text)?;
+        if column > 0 {
+            write!(stdout, "\x1b[{}C", column)?;
+        }
+        stdout.flush()
+    }
+
+    fn read_key() -> io::Result<Key> {
+        let key = match Self::read_byte()? {
+            None | Some(4) => Key::EndOfFile,
+            Some(1) => Key::Home,
+            Some(2) => Key::Left,
+            Some(3) => Key::Interrupt,
+            Some(5) => Key::End,
+            Some(6) => Key::Right,
+            Some(9) => Key::Tab,
+            Some(11) => Key::KillToEnd,
+            Some(14) => Key::Down,
+            Some(16) => Key::Up,
+            Some(21) => Key::KillToStart,
+            Some(b'\r') | Some(b'\n') => Key::Enter,
+            Some(8) | Some(127) => Key::Backspace,
+            Some(27) => Self::read_escape()?,
+            Some(byte) if byte >= 0x80 => Self::read_utf8(byte)?,
+            Some(byte) if byte >= 0x20 => Key::Char(byte as char),
+            Some(_) => Key::Unknown,
+        };
+        Ok(key)
+    }
+
+    fn read_escape() -> io::Result<Key> {
+        let key = match Self::read_byte()? {
+            Some(b'[') | Some(b'O') => match Self::read_byte()? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                Some(digit @ b'0'..=b'9') => {
+                    let mut code = vec![digit];
+                    loop {
+                        match Self::read_byte()? {
+                            Some(b'~') | None => break,
+                            Some(byte) => code.push(byte),
+                        }
+                    }
+                    match &code[..] {
+                        b"1" | b"7" => Key::Home,
+                        b"3" => Key::Delete,
+                        b"4" | b"8" => Key::End,
+                        _ => Key::Unknown,
+                    }
+                }
+                _ => Key::Unknown,
+            },
+            _ => Key::Unknown,
+        };
+        Ok(key)
+    }
+
+    fn read_utf8(first: u8) -> io::Result<Key> {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Ok(Key::Unknown),
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            match Self::read_byte()? {
+                Some(byte) => bytes.push(byte),
+                None => return Ok(Key::Unknown),
+            }
+        }
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s.chars().next().map_or(Key::Unknown, Key::Char)),
+            Err(_) => Ok(Key::Unknown),
+        }
+    }
+
+    fn read_byte() -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match io::stdin().lock().read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod raw {
+    use std::os::raw::c_int;
+
+    const STDIN_FILENO: c_int = 0;
+    const TCSADRAIN: c_int = 1;
+
+    // c_iflag
+    const ICRNL: u32 = 0o400;
+    const IXON: u32 = 0o2000;
+    // c_lflag
+    const ISIG: u32 = 0o1;
+    const ICANON: u32 = 0o2;
+    const ECHO: u32 = 0o10;
+    const IEXTEN: u32 = 0o100000;
+    // c_cc
+    const VTIME: usize = 5;
+    const VMIN: usize = 6;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Termios {
+        c_iflag: u32,
+        c_oflag: u32,
+        c_cflag: u32,
+        c_lflag: u32,
+        c_line: u8,
+        c_cc: [u8; 32],
+        c_ispeed: u32,
+        c_ospeed: u32,
+    }
+
+    extern "C" {
+        fn isatty(fd: c_int) -> c_int;
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+    }
+
+    /// Keeps the terminal in raw mode until dropped.
+    pub struct RawMode {
+        original: Termios,
+    }
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            unsafe {
+                if isatty(STDIN_FILENO) == 0 {
+                    return None;
+                }
+                let mut original = Termios {
+                    c_iflag: 0,
+                    c_oflag: 0,
+                    c_cflag: 0,
+                    c_lflag: 0,
+                    c_line: 0,
+                    c_cc: [0; 32],
+                    c_ispeed: 0,
+                    c_ospeed: 0,
+                };
+                if tcgetattr(STDIN_FILENO, &mut original) != 0 {
+                    return None;
+                }
+                let mut raw = original;
+                raw.c_iflag &= !(ICRNL | IXON);
+                raw.c_lflag &= !(ECHO | ICANON | IEXTEN | ISIG);
+                raw.c_cc[VMIN] = 1;
+                raw.c_cc[VTIME] = 0;
+                if tcsetattr(STDIN_FILENO, TCSADRAIN, &raw) != 0 {
+                    return None;
+                }
+                Some(Self { original })
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe {
+                tcsetattr(STDIN_FILENO, TCSADRAIN, &self.original);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod raw {
+    /// Raw mode is only implemented for Linux terminals.
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> Option<Self> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Completes at the end of `text` among `names`, returning the new line
+    // and what would be listed.
+    fn complete(text: &str, names: &[&str]) -> (String, Option<String>) {
+        let mut line: Vec<char> = text.chars().collect();
+        let mut cursor = line.len();
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let complete = |prefix: &str| -> Vec<String> {
+            names
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .cloned()
+                .collect()
+        };
+        let listed = LineEditor::complete(&mut line, &mut cursor, &complete);
+        assert_eq!(cursor, line.len());
+        (line.into_iter().collect(), listed)
+    }
+
+    #[test]
+    fn completion() {
+        let names = ["range", "read_file", "read_lines", "return"];
+        // a single match is finished with a space
+        assert_eq!(complete("print ra", &names), ("print range ".into(), None));
+        // the common prefix is inserted first
+        assert_eq!(complete("x = rea", &names), ("x = read_".into(), None));
+        // then the candidates are listed
+        assert_eq!(
+            complete("x = read_", &names),
+            ("x = read_".into(), Some("read_file  read_lines".into()))
+        );
+        assert_eq!(
+            complete("r", &names),
+            (
+                "r".into(),
+                Some("range  read_file  read_lines  return".into())
+            )
+        );
+        assert_eq!(complete("zz", &names), ("zz".into(), None));
+        // nothing to complete indents
+        assert_eq!(complete("if (a) ", &names), ("if (a)     ".into(), None));
+
+        let mut line: Vec<char> = "f(ra, 1)".chars().collect();
+        let mut cursor = 4;
+        LineEditor::complete(&mut line, &mut cursor, &|_| vec!["range".into()]);
+        assert_eq!(line.into_iter().collect::<String>(), "f(range , 1)");
+        assert_eq!(cursor, 8);
+    }
+}
@@ -1,5 +1,83 @@
+use stack::Instance;
+
 #[derive(Debug)]
 pub struct Error {
     pub line: u32,
     pub msg: String,
+    pub kind: ErrorKind,
+}
+
+/// What an `Error` unwinds the program for.
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    /// A mistake in the program, reported to the user.
+    Runtime,
+    /// The script called `exit(code)`.
+    Exit(i32),
+    /// The run used up its step budget or passed its deadline.
+    Budget,
+    /// The run was cancelled from outside.
+    Interrupted,
+    /// The script threw a value with `throw`.
+    Thrown(Instance),
+}
+
+impl Error {
+    pub fn new(line: u32, msg: String) -> Self {
+        Self {
+            line,
+            msg,
+            kind: ErrorKind::Runtime,
+        }
+    }
+
+    pub fn exit(line: u32, code: i32) -> Self {
+        Self {
+            line,
+            msg: format!("Exited with status {}.", code),
+            kind: ErrorKind::Exit(code),
+        }
+    }
+
+    pub fn budget(line: u32, msg: String) -> Self {
+        Self {
+            line,
+            msg: format!("Budget exhausted: {}", msg),
+            kind: ErrorKind::Budget,
+        }
+    }
+
+    pub fn thrown(line: u32, value: Instance) -> Self {
+        Self {
+            line,
+            msg: format!("Uncaught exception: {}", value),
+            kind: ErrorKind::Thrown(value),
+        }
+    }
+
+    /// Whether a `catch` clause may handle the error. Exits and exhausted
+    /// limits always end the run.
+    pub fn is_catchable(&self) -> bool {
+        matches!(self.kind, ErrorKind::Runtime | ErrorKind::Thrown(_))
+    }
+
+    /// The value bound by a `catch` clause: what was thrown, or an error value
+    /// with the message and line of a runtime error.
+    pub fn into_value(self) -> Instance {
+        match self.kind {
+            ErrorKind::Thrown(value) => value,
+            _ => Instance::Error {
+                message: self.msg,
+                line: self.line,
+            },
+        }
+    }
+
+    pub fn interrupted(line: u32) -> Self {
+        Self {
+            line,
+            msg: "Interrupted.".into(),
+            kind: ErrorKind::Interrupted,
+        }
+    }
 }
@@ -24,6 +24,23 @@ pub trait Callable: Debug + CallableClone {
         paren: &Token,
         arguments: &[Instance],
     ) -> Result<Instance, Error>;
+
+    /// Values produced when the object is looped over with `for (x in ...)`.
+    /// Objects that are not iterable keep the default.
+    fn iter(&self) -> Option<Box<dyn Iterator<Item = Instance>>> {
+        None
+    }
+
+    /// The name the function was declared with, if it has one.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Approximate bytes the object holds beyond its `Instance` slot, counted
+    /// against the interpreter's memory limit.
+    fn heap_size(&self) -> usize {
+        0
+    }
 }
 
 impl Clone for Box<dyn Callable> {
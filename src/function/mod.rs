@@ -3,23 +3,27 @@ pub mod callable;
 use self::callable::Callable;
 use ast::stmt::Stmt;
 use error::Error;
-use scanner::token::Token;
+use scanner::token::{Token, TokenKind};
 use stack::Instance;
-use worker::Worker;
+use std::path::{Path, PathBuf};
+use worker::{Flow, Worker};
 
 #[derive(Debug, Clone)]
 pub struct AulUserFunction {
     name: Token,
     params: Vec<Token>,
     body: Stmt,
+    // the module it was declared in
+    file: Option<PathBuf>,
 }
 
 impl AulUserFunction {
-    pub fn new(name: &Token, params: &[Token], body: &Stmt) -> Self {
+    pub fn new(name: &Token, params: &[Token], body: &Stmt, file: Option<&Path>) -> Self {
         Self {
             name: name.clone(),
             params: params.to_vec(),
             body: body.clone(),
+            file: file.map(Path::to_path_buf),
         }
     }
 }
@@ -28,20 +32,33 @@ impl Callable for AulUserFunction {
     fn call(
         &self,
         worker: &mut Worker,
-        _paren: &Token,
+        paren: &Token,
         arguments: &[Instance],
     ) -> Result<Instance, Error> {
+        let file = self.file.as_deref();
+        worker.enter_call(self.name().unwrap_or("<lambda>"), file, paren)?;
         worker.stack.push();
-        for (i, p) in self.params.iter().enumerate() {
-            if let Some(ins) = arguments.get(i) {
-                worker.stack.define(p, ins.clone())?;
-            } else {
-                worker.stack.define(p, Instance::Nil)?;
+        let bind = |worker: &mut Worker| -> Result<(), Error> {
+            for (i, p) in self.params.iter().enumerate() {
+                let value = arguments.get(i).cloned().unwrap_or(Instance::Nil);
+                worker.stack.define(p, value)?;
             }
+            Ok(())
+        };
+        // the scope and the call are left even when binding fails
+        let result = bind(worker).and_then(|_| worker.execute(&self.body));
+        worker.stack.pop();
+        worker.leave_call();
+        match result? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Instance::Nil),
         }
+    }
 
-        worker.execute(&self.body)?;
-        worker.stack.pop();
-        Ok(Instance::Nil)
+    fn name(&self) -> Option<&str> {
+        match self.name.kind {
+            TokenKind::Identifier(ref name) => Some(name),
+            _ => None,
+        }
     }
 }
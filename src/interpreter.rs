@@ -1,54 +1,525 @@
+use api::fs::load_fs_api;
 use api::load_std_api;
+use api::process::load_process_api;
+use api::time::load_time_api;
 use ast::stmt::Stmt;
-use error::Error;
+use coverage::Coverage;
+use debugger::Debugger;
+use editor::LineEditor;
+use error::{Error, ErrorKind};
+use interrupt::InterruptGuard;
+use module::Modules;
 use parser::Parser;
-use scanner::token::Token;
-use scanner::Scanner;
-use stack::Stack;
+use profiler::Profiler;
+use resolver::Resolver;
+use scanner::token::{Token, TokenKind};
+use scanner::{Scanner, KEYWORDS};
+use stack::{Instance, Stack};
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead, Read, Write};
-use worker::Worker;
+use std::io::{self, BufReader, Read, Write};
+use std::mem::take;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use worker::{Worker, MAX_CALL_DEPTH};
+
+/// Why a run failed. Compile and runtime errors have already been reported.
+#[derive(Debug)]
+pub enum RunError {
+    /// The source file could not be opened or read.
+    Io { path: String, error: io::Error },
+    /// The source could not be scanned, parsed or resolved.
+    Compile,
+    /// The program failed while executing.
+    Runtime,
+    /// The program called `exit(code)`.
+    Exit(i32),
+    /// The program ran out of steps or time, as set with `set_limits`.
+    Budget,
+    /// The run was stopped through a `CancelToken`.
+    Interrupted,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Io { path, error } => write!(f, "cannot read '{}': {}", path, error),
+            RunError::Compile => write!(f, "compile error"),
+            RunError::Runtime => write!(f, "runtime error"),
+            RunError::Exit(code) => write!(f, "exited with status {}", code),
+            RunError::Budget => write!(f, "budget exhausted"),
+            RunError::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// Which groups of built-ins a script may use. The core language and the std
+/// functions are always there; a disabled group is left out of the global
+/// scope, so using it fails like any undefined variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// `clock()`.
+    pub time: bool,
+    /// The file built-ins and `import`.
+    pub filesystem: bool,
+    /// `argc()`, `argv(i)`, `env(name)` and `exit(code)`.
+    pub process: bool,
+}
+
+impl Capabilities {
+    /// Nothing beyond the core language, for running untrusted scripts.
+    pub fn pure() -> Self {
+        Self {
+            time: false,
+            filesystem: false,
+            process: false,
+        }
+    }
+
+    pub fn all() -> Self {
+        Self {
+            time: true,
+            filesystem: true,
+            process: true,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// How much work a single run may do before it is stopped. Each call to
+/// `run_file`, `run_source` or a REPL entry starts with the full budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Statements and expressions evaluated.
+    pub max_steps: Option<u64>,
+    /// Wall-clock time.
+    pub timeout: Option<Duration>,
+    /// How deep calls may nest before failing with a stack overflow error.
+    /// Raising it may need a bigger native stack.
+    pub max_call_depth: usize,
+    /// Approximate bytes held by variables and scopes. Unlike the other
+    /// limits it covers the whole session, not only the current run.
+    pub max_memory: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            timeout: None,
+            max_call_depth: MAX_CALL_DEPTH,
+            max_memory: None,
+        }
+    }
+}
+
+/// Stops the script an `Interpreter` is running, from any thread. Clones share
+/// the same state.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Makes the current run fail with an "Interrupted" error. When nothing is
+    /// running, the next run is stopped as soon as it starts.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+}
+
+const REPL_HELP: &str = "\
+:env           List the current bindings and their types
+:reset         Start over with a fresh environment
+:load <file>   Run a file in the current session
+:type <expr>   Show the type of an expression
+:quit          Exit the REPL";
 
 pub struct Interpreter {
     error_flag: bool,
     stack: Stack,
+    modules: Modules,
+    args: Vec<String>,
+    capabilities: Capabilities,
+    limits: Limits,
+    cancel: CancelToken,
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    output: Option<Box<dyn Write>>,
 }
 
 impl Interpreter {
-    pub fn run_file(&mut self, file_name: &str) {
-        let mut fh = File::open(file_name).expect("File not found");
+    /// Creates an interpreter whose scripts see `args` through `argc` and `argv`.
+    pub fn new(args: &[String]) -> Self {
+        Self::with_capabilities(args, Capabilities::all())
+    }
+
+    /// Creates an interpreter whose scripts only get the built-ins allowed by
+    /// `capabilities`.
+    pub fn with_capabilities(args: &[String], capabilities: Capabilities) -> Self {
+        Self {
+            error_flag: false,
+            stack: Self::global_stack(args, capabilities),
+            modules: Modules::default(),
+            args: args.to_vec(),
+            capabilities,
+            limits: Limits::default(),
+            cancel: CancelToken::default(),
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            output: None,
+        }
+    }
+
+    /// Sends what scripts print, what the REPL echoes and its commands show,
+    /// and the error reports to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = Some(output);
+    }
+
+    /// Runs scripts under an interactive debugger on stdin and stdout, paused
+    /// before the first statement.
+    pub fn enable_debugger(&mut self) {
+        let input = Box::new(BufReader::new(io::stdin()));
+        self.debugger = Some(Debugger::new(input, Box::new(io::stdout())));
+    }
+
+    /// Times the function calls and counts the lines run from now on, for
+    /// `profile_report`. Does nothing while the debugger is enabled.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// Call counts and times of each function, then the most run lines.
+    pub fn profile_report(&self) -> Option<String> {
+        self.profiler.as_ref().map(Profiler::report)
+    }
+
+    /// The time spent in each call stack, in the collapsed format read by
+    /// flame graph tools.
+    pub fn profile_stacks(&self) -> Option<String> {
+        self.profiler.as_ref().map(Profiler::collapsed_stacks)
+    }
+
+    /// Counts the runs of each line and branch from now on, for
+    /// `coverage_lcov`. Does nothing while the debugger or the profiler is
+    /// enabled.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    /// Adds the counts of an lcov report from earlier runs, so the following
+    /// reports cover them too. Enables coverage.
+    pub fn merge_coverage(&mut self, lcov: &str) -> Result<(), String> {
+        self.coverage
+            .get_or_insert_with(Coverage::default)
+            .merge_lcov(lcov)
+    }
+
+    /// The line and branch counts in the lcov tracefile format.
+    pub fn coverage_lcov(&self) -> Option<String> {
+        self.coverage.as_ref().map(Coverage::lcov)
+    }
+
+    /// The share of lines and branches run in each file, and the lines never
+    /// run.
+    pub fn coverage_summary(&self) -> Option<String> {
+        self.coverage.as_ref().map(Coverage::summary)
+    }
+
+    /// A token that stops whatever this interpreter is running.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Sets the limits for the following runs.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.stack.set_memory_limit(limits.max_memory);
+    }
+
+    /// Approximate bytes currently held by variables and scopes.
+    pub fn memory_used(&self) -> usize {
+        self.stack.memory_used()
+    }
+
+    /// The highest `memory_used` since the interpreter was created or reset.
+    pub fn memory_peak(&self) -> usize {
+        self.stack.memory_peak()
+    }
+
+    /// Adds a directory searched by `import` after the importing file's own.
+    pub fn add_search_path(&mut self, path: PathBuf) {
+        self.modules.add_search_path(path);
+    }
+
+    /// The builtins allowed by `capabilities`, with a global scope on top.
+    pub(crate) fn global_stack(args: &[String], capabilities: Capabilities) -> Stack {
+        let mut stack = Stack::default();
+        if let Err(e) = load_std_api(&mut stack) {
+            println!("Error loading std api: {:?}", e);
+        }
+        if capabilities.time {
+            if let Err(e) = load_time_api(&mut stack) {
+                println!("Error loading time api: {:?}", e);
+            }
+        }
+        if capabilities.process {
+            if let Err(e) = load_process_api(&mut stack, args) {
+                println!("Error loading process api: {:?}", e);
+            }
+        }
+        if capabilities.filesystem {
+            if let Err(e) = load_fs_api(&mut stack) {
+                println!("Error loading fs api: {:?}", e);
+            }
+        }
+        stack.push();
+        stack
+    }
+
+    pub fn run_file(&mut self, file_name: &str) -> Result<(), RunError> {
+        let io_error = |error| RunError::Io {
+            path: file_name.to_string(),
+            error,
+        };
+        let mut fh = File::open(file_name).map_err(io_error)?;
 
         let mut contents = String::new();
-        fh.read_to_string(&mut contents)
-            .expect("Error while reading the file.");
+        fh.read_to_string(&mut contents).map_err(io_error)?;
+
+        // imports inside the file resolve relative to it
+        let path = Path::new(file_name).canonicalize().map_err(io_error)?;
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.set_source(Some(path.clone()), &contents, 1);
+        }
+        self.modules.enter(path);
+        let result = self.run(contents, 1);
+        self.modules.leave(result.is_ok());
+        result
+    }
 
-        self.run(contents, 1).ok();
+    pub fn run_source(&mut self, code: String) -> Result<(), RunError> {
+        self.error_flag = false;
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.set_source(None, &code, 1);
+        }
+        self.run(code, 1)
     }
 
-    pub fn run_prompt(&mut self) {
-        let stdin = io::stdin();
-        let iter = stdin.lock().lines();
+    /// Runs the REPL until the end of input, or until the program calls `exit`.
+    pub fn run_prompt(&mut self) -> Result<(), RunError> {
+        let mut editor = LineEditor::new();
         let mut line_num = 1;
-        let print_head = |num: &u32| {
-            print!("{:>4}> ", num);
-            io::stdout().flush().unwrap();
-        };
-        print_head(&line_num);
-        for line in iter {
+        let mut buffer = String::new();
+        loop {
+            let head = if buffer.is_empty() {
+                line_num.to_string()
+            } else {
+                "...".to_string()
+            };
+            let line = {
+                let stack = &self.stack;
+                let complete = |prefix: &str| Self::complete(stack, prefix);
+                match editor.read_line(&format!("{:>4}> ", head), &complete) {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("Error reading input: {}", e);
+                        break;
+                    }
+                }
+            };
+            editor.add_history(&line);
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                let _interrupt = InterruptGuard::install(self.cancel.flag.clone());
+                if !self.run_command(line.trim())? {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            // a blank line submits whatever has been typed so far
+            let submit = !buffer.is_empty() && line.trim().is_empty();
+            if !buffer.is_empty() {
+                buffer.push('\n');
+            }
+            buffer.push_str(&line);
+            if !submit && Self::is_incomplete(&buffer, line_num) {
+                continue;
+            }
+
+            let code = take(&mut buffer);
+            let line_count = code.lines().count() as u32;
             self.error_flag = false;
-            if self.run(line.unwrap(), line_num).is_ok() {
-                line_num += 1;
+            // Ctrl-C stops the running code instead of the REPL
+            let _interrupt = InterruptGuard::install(self.cancel.flag.clone());
+            match self.run_line(code, line_num) {
+                Ok(()) => line_num += line_count,
+                Err(e @ RunError::Exit(_)) => return Err(e),
+                Err(_) => (),
             }
-            print_head(&line_num);
         }
         println!();
         println!("Exited on end of stream.");
+        Ok(())
     }
 
-    fn run(&mut self, code: String, start_line: u32) -> Result<(), ()> {
-        let Scanner { tokens, .. } = self.scan(code, start_line)?;
-        let Parser { stmts, .. } = self.parse(tokens)?;
-        self.execute(stmts)
+    /// Keywords and names in scope that start with `prefix`.
+    fn complete(stack: &Stack, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = KEYWORDS
+            .iter()
+            .map(|(keyword, _)| keyword.to_string())
+            .filter(|keyword| keyword.starts_with(prefix))
+            .collect();
+        let mut scope = Some(stack);
+        while let Some(stack) = scope {
+            for (name, _) in stack.bindings() {
+                if name.starts_with(prefix) {
+                    names.push(name.to_string());
+                }
+            }
+            scope = stack.parent();
+        }
+        names
+    }
+
+    /// Runs a REPL meta-command. Returns false when the REPL should exit.
+    fn run_command(&mut self, line: &str) -> Result<bool, RunError> {
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        self.error_flag = false;
+        match (command, arg) {
+            (":quit", "") | (":q", "") => return Ok(false),
+            (":help", "") => self.print_line(REPL_HELP),
+            (":env", "") => self.print_env(),
+            (":reset", "") => {
+                self.stack = Self::global_stack(&self.args, self.capabilities);
+                self.stack.set_memory_limit(self.limits.max_memory);
+                self.modules.reset();
+            }
+            (":load", file_name) if !file_name.is_empty() => match self.run_file(file_name) {
+                Err(e @ RunError::Io { .. }) => self.print_line(&format!("Error: {}", e)),
+                Err(e @ RunError::Exit(_)) => return Err(e),
+                _ => (),
+            },
+            (":type", code) if !code.is_empty() => {
+                if let Ok(stmts) = self.compile(code.to_string(), 0, true) {
+                    match self.execute(stmts) {
+                        Ok(Some(value)) => self.print_line(value.type_name()),
+                        Ok(None) => self.print_line("Error: ':type' expects an expression."),
+                        Err(e @ RunError::Exit(_)) => return Err(e),
+                        Err(_) => (),
+                    }
+                }
+            }
+            _ => self.print_line(&format!(
+                "Unknown command '{}'. Commands:\n{}",
+                line, REPL_HELP
+            )),
+        }
+        Ok(true)
+    }
+
+    fn print_env(&mut self) {
+        let mut lines = Vec::new();
+        let mut scope = Some(&self.stack);
+        while let Some(stack) = scope {
+            let label = match stack.height {
+                0 => "builtin".to_string(),
+                1 => "global".to_string(),
+                height => format!("scope {}", height),
+            };
+            for (name, value) in stack.bindings() {
+                match value {
+                    Instance::Function(_) => {
+                        lines.push(format!("[{}] {}: {}", label, name, value.type_name()))
+                    }
+                    _ => lines.push(format!(
+                        "[{}] {}: {} = {}",
+                        label,
+                        name,
+                        value.type_name(),
+                        value
+                    )),
+                }
+            }
+            scope = stack.parent();
+        }
+        for line in lines {
+            self.print_line(&line);
+        }
+    }
+
+    /// Whether `code` stops in the middle of a statement and more lines should
+    /// be read before running it.
+    fn is_incomplete(code: &str, start_line: u32) -> bool {
+        let mut scanner = Scanner::new(code.to_string(), start_line);
+        if scanner.scan_all_tokens().is_err() {
+            return scanner.in_unterminated_string();
+        }
+
+        let mut depth = 0;
+        for token in scanner.tokens.iter() {
+            match token.kind {
+                TokenKind::LeftParen | TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightParen | TokenKind::RightBrace => depth -= 1,
+                _ => (),
+            }
+        }
+        if depth > 0 {
+            return true;
+        }
+
+        let mut parser = Parser::new_repl(scanner.tokens);
+        parser.parse().is_err() && parser.is_eof()
+    }
+
+    fn run(&mut self, code: String, start_line: u32) -> Result<(), RunError> {
+        let stmts = self.compile(code, start_line, false)?;
+        self.execute(stmts)?;
+        Ok(())
+    }
+
+    /// Runs one REPL entry and echoes the value of a trailing bare expression.
+    fn run_line(&mut self, code: String, start_line: u32) -> Result<(), RunError> {
+        if let Some(ref mut debugger) = self.debugger {
+            debugger.set_source(None, &code, start_line);
+        }
+        let stmts = self.compile(code, start_line, true)?;
+        match self.execute(stmts)? {
+            Some(Instance::Nil) | None => (),
+            Some(value) => self.print_line(&value.to_string()),
+        }
+        Ok(())
+    }
+
+    fn compile(
+        &mut self,
+        code: String,
+        start_line: u32,
+        repl: bool,
+    ) -> Result<Vec<Stmt>, RunError> {
+        let Scanner { tokens, .. } = self.scan(code, start_line).or(Err(RunError::Compile))?;
+        let Parser { stmts, .. } = self.parse(tokens, repl).or(Err(RunError::Compile))?;
+        self.resolve(&stmts).or(Err(RunError::Compile))?;
+        Ok(stmts)
     }
 
     fn scan(&mut self, code: String, start_line: u32) -> Result<Scanner, ()> {
@@ -63,8 +534,12 @@ impl Interpreter {
         }
     }
 
-    fn parse(&mut self, tokens: Vec<Token>) -> Result<Parser, ()> {
-        let mut parser = Parser::new(tokens);
+    fn parse(&mut self, tokens: Vec<Token>, repl: bool) -> Result<Parser, ()> {
+        let mut parser = if repl {
+            Parser::new_repl(tokens)
+        } else {
+            Parser::new(tokens)
+        };
         while let Err(e) = parser.parse() {
             self.report_error(e);
             parser.synchronize();
@@ -76,12 +551,8 @@ impl Interpreter {
         }
     }
 
-    fn execute(&mut self, stmts: Vec<Stmt>) -> Result<(), ()> {
-        let result = {
-            let mut worker = Worker::new(&mut self.stack);
-            worker.run(&stmts)
-        };
-        if let Err(e) = result {
+    fn resolve(&mut self, stmts: &[Stmt]) -> Result<(), ()> {
+        if let Err(e) = Resolver::default().resolve(stmts) {
             self.report_error(e);
             Err(())
         } else {
@@ -89,26 +560,401 @@ impl Interpreter {
         }
     }
 
+    fn execute(&mut self, stmts: Vec<Stmt>) -> Result<Option<Instance>, RunError> {
+        let result = {
+            let deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+            let mut worker = Worker::new(&mut self.stack)
+                .with_limits(self.limits.max_steps, deadline)
+                .with_max_call_depth(self.limits.max_call_depth)
+                .with_cancel(&self.cancel.flag);
+            if self.capabilities.filesystem {
+                worker = worker.with_modules(&mut self.modules);
+            }
+            if let Some(ref mut output) = self.output {
+                worker = worker.with_output(output.as_mut());
+            }
+            if let Some(ref mut debugger) = self.debugger {
+                worker = worker.with_hook(debugger);
+            } else if let Some(ref mut profiler) = self.profiler {
+                profiler.begin_run();
+                worker = worker.with_hook(profiler);
+            } else if let Some(ref mut coverage) = self.coverage {
+                coverage.add_program(worker.current_file().map(Path::to_path_buf), &stmts);
+                worker = worker.with_hook(coverage);
+            }
+            worker.run_last_value(&stmts)
+        };
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.end_run();
+        }
+        self.cancel.flag.store(false, Ordering::SeqCst);
+        match result {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                let error = match e.kind {
+                    ErrorKind::Exit(code) => return Err(RunError::Exit(code)),
+                    ErrorKind::Runtime | ErrorKind::Thrown(_) => RunError::Runtime,
+                    ErrorKind::Budget => RunError::Budget,
+                    ErrorKind::Interrupted => RunError::Interrupted,
+                };
+                self.report_error(e);
+                Err(error)
+            }
+        }
+    }
+
     fn report_error(&mut self, e: Error) {
         self.report(e.line, e.msg);
     }
 
     fn report(&mut self, line: u32, msg: String) {
-        println!("[line {}] Error: {}", line, msg);
+        self.print_line(&format!("[line {}] Error: {}", line, msg));
         self.error_flag = true;
     }
+
+    fn print_line(&mut self, line: &str) {
+        match self.output {
+            Some(ref mut output) => {
+                writeln!(output, "{}", line).ok();
+            }
+            None => println!("{}", line),
+        }
+    }
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
-        let mut stack = Stack::default();
-        if let Err(e) = load_std_api(&mut stack) {
-            println!("Error loading std api: {:?}", e);
+        Self::new(&[])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use testing::SharedOutput;
+
+    #[test]
+    fn incomplete_input() {
+        assert!(Interpreter::is_incomplete("fun f() {", 1));
+        assert!(Interpreter::is_incomplete("print (1 +", 1));
+        assert!(Interpreter::is_incomplete("var s = \"abc", 1));
+        assert!(Interpreter::is_incomplete("print 1", 1));
+        assert!(!Interpreter::is_incomplete("print 1;", 1));
+        assert!(!Interpreter::is_incomplete("1 + 2", 1));
+        assert!(!Interpreter::is_incomplete("print 1);", 1));
+        assert!(!Interpreter::is_incomplete("var 1 = 2;", 1));
+    }
+
+    #[test]
+    fn unreadable_file() {
+        let missing = env::temp_dir().join("wendell_missing_test.aul");
+        fs::remove_file(&missing).ok();
+        let path = missing.display().to_string();
+        match Interpreter::default().run_file(&path) {
+            Err(RunError::Io {
+                path: reported,
+                error,
+            }) => {
+                assert_eq!(reported, path);
+                assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            }
+            other => panic!("missing file was run: {:?}", other),
         }
-        stack.push();
-        Self {
-            error_flag: false,
-            stack,
+        // a directory opens but cannot be read
+        let dir = env::temp_dir().display().to_string();
+        match Interpreter::default().run_file(&dir) {
+            Err(RunError::Io { .. }) => (),
+            other => panic!("directory was run: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_imports() {
+        let dir = env::temp_dir().join("wendell_nested_import_test");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.aul"), "import \"lib/a.aul\";\nprint a();").unwrap();
+        fs::write(
+            dir.join("lib/a.aul"),
+            "import \"b.aul\";\nfun a() { return b(); }",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/b.aul"), "fun b() { return \"lib\"; }").unwrap();
+        // not the one next to the importing file
+        fs::write(dir.join("b.aul"), "fun b() { return \"main\"; }").unwrap();
+
+        let output = SharedOutput::default();
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(output.clone()));
+        intr.run_file(&dir.join("main.aul").display().to_string())
+            .unwrap();
+        assert_eq!(output.text(), "lib\n");
+    }
+
+    #[test]
+    fn process_builtins() {
+        env::set_var("WENDELL_TEST_VAR", "set");
+        env::remove_var("WENDELL_TEST_UNSET");
+        let output = SharedOutput::default();
+        let mut intr = Interpreter::new(&["a".into(), "b".into()]);
+        intr.set_output(Box::new(output.clone()));
+        intr.run_source(
+            "print argc();\nprint argv(1);\nprint argv(2);\n\
+             print env(\"WENDELL_TEST_VAR\");\nprint env(\"WENDELL_TEST_UNSET\");"
+                .into(),
+        )
+        .unwrap();
+        assert_eq!(output.take(), "2\nb\nnil\nset\nnil\n");
+        assert!(intr.run_source("argv(-1);".into()).is_err());
+        assert!(intr.run_source("env(1);".into()).is_err());
+    }
+
+    #[test]
+    fn exit() {
+        let output = SharedOutput::default();
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(output.clone()));
+        match intr.run_source("exit(3);\nprint 1;".into()) {
+            Err(RunError::Exit(3)) => (),
+            other => panic!("exit(3) gave {:?}", other),
+        }
+        match intr.run_source("exit();".into()) {
+            Err(RunError::Exit(0)) => (),
+            other => panic!("exit() gave {:?}", other),
+        }
+        // neither catch nor the code after the try runs
+        let code = "fun f() { exit(4); }\n\
+                    try { f(); } catch (e) { print \"caught\"; }\n\
+                    print \"after\";";
+        match intr.run_source(code.into()) {
+            Err(RunError::Exit(4)) => (),
+            other => panic!("exit(4) gave {:?}", other),
+        }
+        assert_eq!(output.take(), "");
+        match intr.run_source("exit(1.5);".into()) {
+            Err(RunError::Runtime) => (),
+            other => panic!("exit(1.5) gave {:?}", other),
+        }
+    }
+
+    #[test]
+    fn echo() {
+        let output = SharedOutput::default();
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(output.clone()));
+        intr.run_line("var a = 2;\nprint a;\nfun f() {}".into(), 1)
+            .unwrap();
+        assert_eq!(output.take(), "2\n");
+        intr.run_line("a * 3".into(), 4).unwrap();
+        assert_eq!(output.take(), "6\n");
+        intr.run_line("var s = \"x\"; s".into(), 5).unwrap();
+        assert_eq!(output.take(), "x\n");
+        // nil is not echoed
+        intr.run_line("f()".into(), 6).unwrap();
+        assert_eq!(output.take(), "");
+    }
+
+    fn lookup(intr: &Interpreter, name: &str) -> Option<Instance> {
+        let name = Token::new(TokenKind::Identifier(name.into()), 0);
+        intr.stack.get(&name).ok()
+    }
+
+    #[test]
+    fn completion_candidates() {
+        let mut intr = Interpreter::default();
+        intr.run_source("var total = 1;\nfun tally() {}".into())
+            .unwrap();
+        let mut names = Interpreter::complete(&intr.stack, "t");
+        names.sort();
+        assert_eq!(
+            names,
+            ["tally", "test", "this", "throw", "total", "true", "try"]
+        );
+        assert!(Interpreter::complete(&intr.stack, "zz").is_empty());
+    }
+
+    #[test]
+    fn commands() {
+        let output = SharedOutput::default();
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(output.clone()));
+        intr.run_line("var a = 1;\nfun f() {}".into(), 1).unwrap();
+
+        assert!(intr.run_command(":env").unwrap());
+        let env = output.take();
+        assert!(env.contains("[global] a: Number = 1\n"));
+        assert!(env.contains("[global] f: Function\n"));
+        assert!(env.contains("[builtin] range: Function\n"));
+
+        intr.run_command(":type a + 1").unwrap();
+        intr.run_command(":type \"s\"").unwrap();
+        intr.run_command(":type var b = 1;").unwrap();
+        assert_eq!(
+            output.take(),
+            "Number\nString\nError: ':type' expects an expression.\n"
+        );
+
+        intr.run_command(":reset").unwrap();
+        assert!(lookup(&intr, "a").is_none());
+        assert!(lookup(&intr, "range").is_some());
+        intr.run_command(":env").unwrap();
+        assert!(!output.take().contains("[global]"));
+
+        let file = env::temp_dir().join("wendell_load_test.aul");
+        fs::write(&file, "var loaded = 42;").unwrap();
+        intr.run_command(&format!(":load {}", file.display()))
+            .unwrap();
+        assert!(lookup(&intr, "loaded").is_some());
+        fs::remove_file(&file).unwrap();
+        intr.run_command(&format!(":load {}", file.display()))
+            .unwrap();
+        assert!(output.take().starts_with("Error: cannot read"));
+
+        intr.run_command(":nope").unwrap();
+        assert!(output.take().starts_with("Unknown command ':nope'."));
+        assert!(!intr.run_command(":quit").unwrap());
+    }
+
+    #[test]
+    fn pure_profile() {
+        let file = env::temp_dir().join("wendell_pure_test.aul");
+        fs::write(&file, "var leaked = 1;").unwrap();
+        let path = file.display().to_string();
+        let denied = [
+            format!("read_file(\"{}\");", path),
+            format!("write_file(\"{}\", \"\");", path),
+            format!("import \"{}\";", path),
+            "env(\"HOME\");".to_string(),
+            "clock();".to_string(),
+        ];
+
+        let mut intr = Interpreter::with_capabilities(&[], Capabilities::pure());
+        for code in denied.iter() {
+            match intr.run_source(code.clone()) {
+                Err(RunError::Runtime) => (),
+                other => panic!("'{}' was allowed: {:?}", code, other),
+            }
+        }
+        assert!(intr
+            .run_source("for (i in range(3)) print i;".into())
+            .is_ok());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "var leaked = 1;");
+
+        let mut intr = Interpreter::default();
+        assert!(intr.run_source(denied[0].clone()).is_ok());
+    }
+
+    #[test]
+    fn limits() {
+        let mut intr = Interpreter::default();
+        intr.run_source("var a = 0;".into()).unwrap();
+        intr.set_limits(Limits {
+            max_steps: Some(10_000),
+            ..Limits::default()
+        });
+        match intr.run_source("while (true) { a = a + 1; }".into()) {
+            Err(RunError::Budget) => (),
+            other => panic!("loop was not stopped: {:?}", other),
+        }
+        intr.set_limits(Limits {
+            timeout: Some(Duration::from_millis(10)),
+            ..Limits::default()
+        });
+        match intr.run_source("while (true) {}".into()) {
+            Err(RunError::Budget) => (),
+            other => panic!("loop was not stopped: {:?}", other),
+        }
+        // the globals survive and the next run gets a fresh budget
+        intr.run_source("while (a > 0) { a = a - 1; }".into())
+            .unwrap();
+    }
+
+    #[test]
+    fn coverage_of_last_statement() {
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(SharedOutput::default()));
+        intr.enable_coverage();
+        intr.run_source("fun f() {\n  print 1;\n}\nf();".into())
+            .unwrap();
+        let lcov = intr.coverage_lcov().unwrap();
+        assert!(lcov.contains("DA:1,1\nDA:2,1\nDA:4,1\nLF:3\nLH:3\n"));
+    }
+
+    #[test]
+    fn profile_of_last_statement() {
+        let mut intr = Interpreter::default();
+        intr.set_output(Box::new(SharedOutput::default()));
+        intr.enable_profiler();
+        intr.run_source("fun f() {\n  print 1;\n}\nf();".into())
+            .unwrap();
+        let report = intr.profile_report().unwrap();
+        // each line ran once
+        let ran = |line: &str| {
+            report
+                .lines()
+                .any(|row| row.split_whitespace().collect::<Vec<_>>() == ["line", line, "1"])
+        };
+        assert!(ran("1") && ran("2") && ran("4"));
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut intr = Interpreter::default();
+        let start = intr.memory_used();
+        intr.set_limits(Limits {
+            max_memory: Some(start + 100_000),
+            ..Limits::default()
+        });
+        intr.run_source("var s = \"ab\";".into()).unwrap();
+        match intr.run_source("while (true) s = s + s;".into()) {
+            Err(RunError::Budget) => (),
+            other => panic!("string kept growing: {:?}", other),
+        }
+        assert!(intr.memory_used() <= start + 100_000);
+        assert!(intr.memory_peak() > start + 50_000);
+        intr.run_source("s = nil;".into()).unwrap();
+        assert!(intr.memory_used() < start + 1_000);
+    }
+
+    #[test]
+    fn memory_limit_in_arguments() {
+        let mut intr = Interpreter::default();
+        let start = intr.memory_used();
+        intr.set_limits(Limits {
+            max_memory: Some(start + 100_000),
+            ..Limits::default()
+        });
+        intr.run_source("var s = \"ab\";\nfor (i in range(14)) s = s + s;".into())
+            .unwrap();
+        match intr.run_source("fun f(a, b, c) {}\nf(s, s, s);".into()) {
+            Err(RunError::Budget) => (),
+            other => panic!("arguments were bound: {:?}", other),
+        }
+        // the call's scope is gone, so globals still land in the global scope
+        assert_eq!(intr.stack.height, 1);
+        intr.run_source("s = nil;\nvar after = f(1, 2, 3);".into())
+            .unwrap();
+        assert_eq!(intr.stack.height, 1);
+        assert!(intr.memory_used() < start + 1_000);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut intr = Interpreter::default();
+        intr.run_source("var a = 0;".into()).unwrap();
+        let token = intr.cancel_token();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        });
+        match intr.run_source("while (true) a = a + 1;".into()) {
+            Err(RunError::Interrupted) => (),
+            other => panic!("loop was not interrupted: {:?}", other),
         }
+        canceller.join().unwrap();
+        assert!(!intr.cancel_token().is_cancelled());
+        intr.run_source("while (a > 0) a = a - 1;".into()).unwrap();
     }
 }
@@ -0,0 +1,72 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// Turns Ctrl-C into a cancel request for as long as it is alive, instead of
+/// killing the process.
+pub struct InterruptGuard {
+    _flag: Arc<AtomicBool>,
+}
+
+impl InterruptGuard {
+    pub fn install(flag: Arc<AtomicBool>) -> Self {
+        imp::install(&flag);
+        Self { _flag: flag }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        imp::uninstall();
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::raw::c_int;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+    const SIGINT: c_int = 2;
+    const SIG_DFL: usize = 0;
+
+    // the flag set by the handler, kept alive by the guard while installed
+    static FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(ptr::null_mut());
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_sigint(_signum: c_int) {
+        let flag = FLAG.load(Ordering::SeqCst);
+        if !flag.is_null() {
+            unsafe { (*flag).store(true, Ordering::SeqCst) };
+        }
+    }
+
+    pub fn install(flag: &AtomicBool) {
+        FLAG.store(
+            flag as *const AtomicBool as *mut AtomicBool,
+            Ordering::SeqCst,
+        );
+        unsafe {
+            signal(SIGINT, on_sigint as extern "C" fn(c_int) as usize);
+        }
+    }
+
+    pub fn uninstall() {
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+        }
+        FLAG.store(ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::sync::atomic::AtomicBool;
+
+    /// Ctrl-C keeps its default behavior outside Unix.
+    pub fn install(_flag: &AtomicBool) {}
+
+    pub fn uninstall() {}
+}
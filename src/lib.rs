@@ -1,13 +1,26 @@
 mod api;
 mod ast;
+mod coverage;
+mod debugger;
+mod editor;
 mod error;
 mod function;
+mod interrupt;
+mod json;
+mod module;
 mod parser;
+mod profiler;
+mod resolver;
 mod scanner;
 mod stack;
 mod worker;
 
+#[cfg(test)]
+mod testing;
+
+pub mod dap;
 pub mod interpreter;
+pub mod lsp;
 
 #[cfg(test)]
 mod test {
@@ -0,0 +1,333 @@
+use ast::arm::MatchArm;
+use ast::expr::*;
+use ast::stmt::*;
+use scanner::token::{Token, TokenKind};
+use std::collections::HashMap;
+
+/// What introduced a name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Constant,
+    Function,
+    /// A parameter of the named function.
+    Parameter(String),
+    LoopVariable,
+    CaughtError,
+}
+
+impl SymbolKind {
+    pub fn describe(&self) -> String {
+        match self {
+            SymbolKind::Variable => "variable".into(),
+            SymbolKind::Constant => "constant".into(),
+            SymbolKind::Function => "function".into(),
+            SymbolKind::Parameter(function) => format!("parameter of {}", function),
+            SymbolKind::LoopVariable => "loop variable".into(),
+            SymbolKind::CaughtError => "caught error".into(),
+        }
+    }
+}
+
+/// A declared name and the places it is used.
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub declaration: Token,
+    /// Whether it is declared outside of any block or function.
+    pub top_level: bool,
+    /// Uses of the name, not counting the declaration.
+    pub references: Vec<Token>,
+}
+
+/// Links every use of a name in a parsed file to its declaration, following
+/// the same scopes as the resolver. Names used before a global declaration,
+/// like a function called from one declared earlier, link to that global.
+/// Built-ins have no symbol.
+pub struct Index {
+    pub symbols: Vec<Symbol>,
+    scopes: Vec<HashMap<String, usize>>,
+    // names of the functions being indexed, innermost last
+    functions: Vec<String>,
+    // uses with nothing in scope, looked up in the globals at the end
+    unresolved: Vec<Token>,
+}
+
+impl Index {
+    pub fn build(stmts: &[Stmt]) -> Self {
+        let mut index = Self {
+            symbols: Vec::new(),
+            scopes: vec![HashMap::new()],
+            functions: Vec::new(),
+            unresolved: Vec::new(),
+        };
+        for stmt in stmts {
+            stmt.accept(&mut index);
+        }
+        for name in index.unresolved.split_off(0) {
+            if let Some(&id) = index.scopes[0].get(name_of(&name)) {
+                index.symbols[id].references.push(name);
+            }
+        }
+        index
+    }
+
+    /// The symbol whose declaration or use covers `column` on `line`, along
+    /// with that token.
+    pub fn symbol_at(&self, line: u32, column: u32) -> Option<(&Symbol, &Token)> {
+        let covers = |token: &Token| {
+            token.line == line
+                && token.column <= column
+                && column <= token.column + name_of(token).chars().count() as u32
+        };
+        self.symbols.iter().find_map(|symbol| {
+            Some(&symbol.declaration)
+                .into_iter()
+                .chain(symbol.references.iter())
+                .find(|token| covers(token))
+                .map(|token| (symbol, token))
+        })
+    }
+
+    fn declare(&mut self, name: &Token, kind: SymbolKind) {
+        let top_level = self.scopes.len() == 1;
+        self.symbols.push(Symbol {
+            name: name_of(name).to_string(),
+            kind,
+            declaration: name.clone(),
+            top_level,
+            references: Vec::new(),
+        });
+        let id = self.symbols.len() - 1;
+        let scope = self.scopes.last_mut().expect("Index has no scope");
+        scope.insert(name_of(name).to_string(), id);
+    }
+
+    fn reference(&mut self, name: &Token) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name_of(name)).cloned());
+        match found {
+            Some(id) => self.symbols[id].references.push(name.clone()),
+            None => self.unresolved.push(name.clone()),
+        }
+    }
+
+    fn in_scope<F: FnOnce(&mut Self)>(&mut self, body: F) {
+        self.scopes.push(HashMap::new());
+        body(self);
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, name: String, params: &[Token], body: &Stmt) {
+        self.functions.push(name);
+        self.in_scope(|index| {
+            let function = index.functions.last().cloned().unwrap_or_default();
+            for param in params {
+                index.declare(param, SymbolKind::Parameter(function.clone()));
+            }
+            body.accept(index);
+        });
+        self.functions.pop();
+    }
+}
+
+pub fn name_of(name: &Token) -> &str {
+    match name.kind {
+        TokenKind::Identifier(ref name) => name,
+        _ => "",
+    }
+}
+
+impl ExprVisitor<()> for Index {
+    fn visit_assign(&mut self, name: &Token, value: &Expr) {
+        value.accept(self);
+        self.reference(name);
+    }
+
+    fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) {
+        left.accept(self);
+        right.accept(self);
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) {
+        expression.accept(self);
+    }
+
+    fn visit_literal(&mut self, _value: &Token) {}
+
+    fn visit_unary(&mut self, _operator: &Token, right: &Expr) {
+        right.accept(self);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        callee.accept(self);
+        for arg in arguments {
+            arg.accept(self);
+        }
+    }
+
+    fn visit_variable(&mut self, name: &Token) {
+        self.reference(name);
+    }
+
+    fn visit_lambda(&mut self, _keyword: &Token, params: &[Token], body: &Stmt) {
+        self.function("<lambda>".into(), params, body);
+    }
+
+    fn visit_get(&mut self, object: &Expr, _name: &Token) {
+        object.accept(self);
+    }
+
+    fn visit_empty_expr(&mut self) {}
+}
+
+impl StmtVisitor<()> for Index {
+    fn visit_var(&mut self, name: &Token, initializer: &Expr) {
+        initializer.accept(self);
+        self.declare(name, SymbolKind::Variable);
+    }
+
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) {
+        initializer.accept(self);
+        self.declare(name, SymbolKind::Constant);
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, _path: &Token) {}
+
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.in_scope(|index| {
+            for stmt in statements {
+                stmt.accept(index);
+            }
+        });
+    }
+
+    fn visit_if(
+        &mut self,
+        _line_number: &u32,
+        condition: &Expr,
+        then_block: &Stmt,
+        else_block: &Stmt,
+    ) {
+        condition.accept(self);
+        then_block.accept(self);
+        else_block.accept(self);
+    }
+
+    fn visit_while(&mut self, _line_number: &u32, condition: &Expr, body: &Stmt, increment: &Stmt) {
+        condition.accept(self);
+        body.accept(self);
+        increment.accept(self);
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        iterable.accept(self);
+        self.in_scope(|index| {
+            index.declare(name, SymbolKind::LoopVariable);
+            body.accept(index);
+        });
+    }
+
+    fn visit_match(&mut self, _keyword: &Token, value: &Expr, arms: &[MatchArm]) {
+        value.accept(self);
+        for arm in arms {
+            for pattern in arm.patterns.iter() {
+                pattern.accept(self);
+            }
+            if let Some(ref guard) = arm.guard {
+                guard.accept(self);
+            }
+            arm.body.accept(self);
+        }
+    }
+
+    fn visit_break(&mut self, _keyword: &Token) {}
+
+    fn visit_continue(&mut self, _keyword: &Token) {}
+
+    fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt) {
+        self.declare(name, SymbolKind::Function);
+        self.function(name_of(name).to_string(), params, body);
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) {
+        value.accept(self);
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) {
+        value.accept(self);
+    }
+
+    fn visit_try(
+        &mut self,
+        _keyword: &Token,
+        body: &Stmt,
+        catch_name: &Option<Token>,
+        catch_body: &Stmt,
+        finally_body: &Stmt,
+    ) {
+        body.accept(self);
+        self.in_scope(|index| {
+            if let Some(name) = catch_name {
+                index.declare(name, SymbolKind::CaughtError);
+            }
+            catch_body.accept(index);
+        });
+        finally_body.accept(self);
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) {
+        expression.accept(self);
+    }
+
+    fn visit_print(&mut self, expression: &Expr) {
+        expression.accept(self);
+    }
+
+    fn visit_empty_stmt(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use module::compile;
+
+    #[test]
+    fn scopes_and_forward_uses() {
+        let code = "var a = 1;\n\
+                    fun f(a) {\n  \
+                      return a + g();\n\
+                    }\n\
+                    fun g() { return a; }\n\
+                    for (i in range(a)) print f(i);";
+        let index = Index::build(&compile(code.into()).unwrap());
+
+        let uses = |name: &str, kind: SymbolKind| -> Vec<(u32, u32)> {
+            let symbol = index
+                .symbols
+                .iter()
+                .find(|symbol| symbol.name == name && symbol.kind == kind)
+                .unwrap();
+            symbol
+                .references
+                .iter()
+                .map(|token| (token.line, token.column))
+                .collect()
+        };
+        assert_eq!(uses("a", SymbolKind::Variable), [(5, 17), (6, 16)]);
+        assert_eq!(uses("a", SymbolKind::Parameter("f".into())), [(3, 9)]);
+        assert_eq!(uses("g", SymbolKind::Function), [(3, 13)]);
+        assert_eq!(uses("f", SymbolKind::Function), [(6, 26)]);
+        assert_eq!(uses("i", SymbolKind::LoopVariable), [(6, 28)]);
+
+        let (symbol, token) = index.symbol_at(3, 14).unwrap();
+        assert_eq!((symbol.name.as_str(), symbol.declaration.line), ("g", 5));
+        assert_eq!(token.column, 13);
+        assert!(index.symbol_at(6, 20).is_none());
+        assert!(index.symbols.iter().all(|symbol| symbol.name != "range"));
+    }
+}
@@ -0,0 +1,606 @@
+mod index;
+
+use self::index::{name_of, Index, Symbol, SymbolKind};
+use error::Error;
+use interpreter::{Capabilities, Interpreter};
+use json::{read_message, write_message, Json};
+use parser::Parser;
+use resolver::Resolver;
+use scanner::token::Token;
+use scanner::{Scanner, KEYWORDS};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+// JSON-RPC error codes for a message that is not valid JSON and for a request
+// the server does not handle
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+// LSP symbol and completion item kinds
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const SYMBOL_CONSTANT: u32 = 14;
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+
+/// An open file, analysed each time it changes.
+struct Document {
+    lines: Vec<String>,
+    errors: Vec<Error>,
+    index: Index,
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let mut errors = Vec::new();
+        let mut scanner = Scanner::new(text.to_string(), 1);
+        while let Err(e) = scanner.scan_all_tokens() {
+            errors.push(e);
+        }
+        // parse what could be scanned, so that the rest of the file still
+        // gets symbols
+        let mut parser = Parser::new(scanner.tokens);
+        while let Err(e) = parser.parse() {
+            errors.push(e);
+            parser.synchronize();
+        }
+        if errors.is_empty() {
+            if let Err(e) = Resolver::default().resolve(&parser.stmts) {
+                errors.push(e);
+            }
+        }
+        Self {
+            lines: text.lines().map(String::from).collect(),
+            errors,
+            index: Index::build(&parser.stmts),
+        }
+    }
+
+    fn line(&self, line: u32) -> &str {
+        line.checked_sub(1)
+            .and_then(|i| self.lines.get(i as usize))
+            .map_or("", String::as_str)
+    }
+
+    /// An LSP position, in UTF-16 code units, for a column in characters.
+    fn position(&self, line: u32, column: u32) -> Json {
+        let character: usize = self
+            .line(line)
+            .chars()
+            .take(column as usize)
+            .map(char::len_utf16)
+            .sum();
+        Json::object(vec![
+            ("line", line.saturating_sub(1).into()),
+            ("character", character.into()),
+        ])
+    }
+
+    /// The column in characters of an LSP position.
+    fn column(&self, line: u32, character: u64) -> u32 {
+        let mut units = 0;
+        let mut column = 0;
+        for c in self.line(line).chars() {
+            if units >= character as usize {
+                break;
+            }
+            units += c.len_utf16();
+            column += 1;
+        }
+        column
+    }
+
+    fn range(&self, token: &Token) -> Json {
+        let end = token.column + name_of(token).chars().count() as u32;
+        Json::object(vec![
+            ("start", self.position(token.line, token.column)),
+            ("end", self.position(token.line, end)),
+        ])
+    }
+
+    fn line_range(&self, line: u32) -> Json {
+        let end = self.line(line).chars().count() as u32;
+        Json::object(vec![
+            ("start", self.position(line, 0)),
+            ("end", self.position(line, end)),
+        ])
+    }
+
+    fn diagnostics(&self) -> Json {
+        self.errors
+            .iter()
+            .map(|e| {
+                Json::object(vec![
+                    ("range", self.line_range(e.line)),
+                    ("severity", 1u32.into()),
+                    ("source", "wendell".into()),
+                    ("message", e.msg.clone().into()),
+                ])
+            })
+            .collect::<Vec<Json>>()
+            .into()
+    }
+
+    /// The symbol at the position of a request.
+    fn symbol_at(&self, params: &Json) -> Option<(&Symbol, &Token)> {
+        let position = params.get("position")?;
+        let line = position.get("line").and_then(Json::as_u64)? as u32 + 1;
+        let character = position.get("character").and_then(Json::as_u64)?;
+        self.index.symbol_at(line, self.column(line, character))
+    }
+
+    /// The declaration of `symbol` as shown on hover: its line of code and
+    /// what it is.
+    fn describe(&self, symbol: &Symbol) -> String {
+        format!(
+            "```wendell\n{}\n```\n{}, declared on line {}",
+            self.line(symbol.declaration.line).trim(),
+            symbol.kind.describe(),
+            symbol.declaration.line
+        )
+    }
+}
+
+/// A Language Server Protocol server for wendell files, answering the
+/// requests read from `input`. Files are synchronised in full on each change.
+pub struct LspServer {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    documents: HashMap<String, Document>,
+    // names of the built-in functions, for completion
+    builtins: Vec<String>,
+}
+
+impl LspServer {
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        let stack = Interpreter::global_stack(&[], Capabilities::all());
+        let builtins = stack
+            .parent()
+            .map(|builtins| {
+                builtins
+                    .bindings()
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            input,
+            output,
+            documents: HashMap::new(),
+            builtins,
+        }
+    }
+
+    /// Serves requests until the client sends `exit` or closes the input.
+    pub fn run(&mut self) {
+        while let Ok(Some(message)) = read_message(&mut self.input) {
+            let message = match message {
+                Ok(message) => message,
+                Err(msg) => {
+                    // the id is unknown when the message cannot be read
+                    let response = error_response(Json::Null, PARSE_ERROR, msg);
+                    self.send(&response);
+                    continue;
+                }
+            };
+            let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+            if method == "exit" {
+                return;
+            }
+            let params = message.get("params").cloned().unwrap_or(Json::Null);
+            let result = self.handle(method, &params);
+            if let Some(id) = message.get("id") {
+                let response = match result {
+                    Some(result) => Json::object(vec![
+                        ("jsonrpc", "2.0".into()),
+                        ("id", id.clone()),
+                        ("result", result),
+                    ]),
+                    None => error_response(
+                        id.clone(),
+                        METHOD_NOT_FOUND,
+                        format!("Unsupported method '{}'.", method),
+                    ),
+                };
+                self.send(&response);
+            }
+        }
+    }
+
+    fn send(&mut self, message: &Json) {
+        // a closed output shows up as the end of input soon after
+        write_message(&mut self.output, message).ok();
+    }
+
+    /// Handles a request or notification, returning the result of a request
+    /// or `None` when the method is not supported.
+    fn handle(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        match method {
+            "initialize" => Some(Json::object(vec![
+                (
+                    "capabilities",
+                    Json::object(vec![
+                        ("textDocumentSync", 1u32.into()),
+                        ("definitionProvider", true.into()),
+                        ("referencesProvider", true.into()),
+                        ("hoverProvider", true.into()),
+                        ("documentSymbolProvider", true.into()),
+                        ("completionProvider", Json::object(vec![])),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object(vec![
+                        ("name", "wendell".into()),
+                        ("version", env!("CARGO_PKG_VERSION").into()),
+                    ]),
+                ),
+            ])),
+            "shutdown" => Some(Json::Null),
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                self.update(uri, text);
+                Some(Json::Null)
+            }
+            "textDocument/didChange" => {
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                self.update(uri, text);
+                Some(Json::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish(&uri, Json::Array(Vec::new()));
+                Some(Json::Null)
+            }
+            "textDocument/definition"
+            | "textDocument/references"
+            | "textDocument/hover"
+            | "textDocument/documentSymbol"
+            | "textDocument/completion" => Some(match self.documents.get(&uri) {
+                Some(document) => self.query(document, &uri, method, params),
+                // nothing is known about files that were not opened
+                None => Json::Null,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Answers a request about an open file.
+    fn query(&self, document: &Document, uri: &str, method: &str, params: &Json) -> Json {
+        match method {
+            "textDocument/definition" => match document.symbol_at(params) {
+                Some((symbol, _)) => location(uri, document.range(&symbol.declaration)),
+                None => Json::Null,
+            },
+            "textDocument/references" => {
+                let include_declaration = params
+                    .get("context")
+                    .and_then(|context| context.get("includeDeclaration"))
+                    .and_then(Json::as_bool)
+                    .unwrap_or(true);
+                let mut locations = Vec::new();
+                if let Some((symbol, _)) = document.symbol_at(params) {
+                    if include_declaration {
+                        locations.push(&symbol.declaration);
+                    }
+                    locations.extend(symbol.references.iter());
+                }
+                locations.sort_by_key(|token| (token.line, token.column));
+                locations
+                    .into_iter()
+                    .map(|token| location(uri, document.range(token)))
+                    .collect::<Vec<Json>>()
+                    .into()
+            }
+            "textDocument/hover" => match document.symbol_at(params) {
+                Some((symbol, token)) => Json::object(vec![
+                    (
+                        "contents",
+                        Json::object(vec![
+                            ("kind", "markdown".into()),
+                            ("value", document.describe(symbol).into()),
+                        ]),
+                    ),
+                    ("range", document.range(token)),
+                ]),
+                None => Json::Null,
+            },
+            "textDocument/documentSymbol" => Self::document_symbols(document),
+            "textDocument/completion" => self.completions(document),
+            _ => Json::Null,
+        }
+    }
+
+    fn update(&mut self, uri: String, text: &str) {
+        let document = Document::new(text);
+        let diagnostics = document.diagnostics();
+        self.documents.insert(uri.clone(), document);
+        self.publish(&uri, diagnostics);
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Json) {
+        let notification = Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics)]),
+            ),
+        ]);
+        self.send(&notification);
+    }
+
+    /// Every function, and the variables and constants declared at the top
+    /// level.
+    fn document_symbols(document: &Document) -> Json {
+        document
+            .index
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                let kind = match symbol.kind {
+                    SymbolKind::Function => SYMBOL_FUNCTION,
+                    SymbolKind::Variable if symbol.top_level => SYMBOL_VARIABLE,
+                    SymbolKind::Constant if symbol.top_level => SYMBOL_CONSTANT,
+                    _ => return None,
+                };
+                let range = document.range(&symbol.declaration);
+                Some(Json::object(vec![
+                    ("name", symbol.name.clone().into()),
+                    (
+                        "detail",
+                        document.line(symbol.declaration.line).trim().into(),
+                    ),
+                    ("kind", kind.into()),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ]))
+            })
+            .collect::<Vec<Json>>()
+            .into()
+    }
+
+    /// Keywords, built-ins and the names declared in the file. The editor
+    /// narrows them down to what is being typed.
+    fn completions(&self, document: &Document) -> Json {
+        let mut items = Vec::new();
+        let mut seen = Vec::new();
+        let mut add = |label: &str, kind: u32, detail: String| {
+            if !seen.iter().any(|name: &String| name == label) {
+                seen.push(label.to_string());
+                items.push(Json::object(vec![
+                    ("label", label.into()),
+                    ("kind", kind.into()),
+                    ("detail", detail.into()),
+                ]));
+            }
+        };
+        for symbol in document.index.symbols.iter() {
+            let kind = match symbol.kind {
+                SymbolKind::Function => COMPLETION_FUNCTION,
+                _ => COMPLETION_VARIABLE,
+            };
+            add(&symbol.name, kind, symbol.kind.describe());
+        }
+        for name in self.builtins.iter() {
+            add(name, COMPLETION_FUNCTION, "built-in".into());
+        }
+        for (keyword, _) in KEYWORDS {
+            add(keyword, COMPLETION_KEYWORD, "keyword".into());
+        }
+        items.into()
+    }
+}
+
+fn location(uri: &str, range: Json) -> Json {
+    Json::object(vec![("uri", uri.into()), ("range", range)])
+}
+
+/// A JSON-RPC response reporting that the request `id` failed.
+fn error_response(id: Json, code: i32, message: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            Json::object(vec![("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use testing::SharedOutput;
+
+    const URI: &str = "file:///test.aul";
+
+    fn request(id: u32, method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn at(line: u32, character: u32) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into())])),
+            (
+                "position",
+                Json::object(vec![("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    fn change(text: &str) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", URI.into())])),
+            (
+                "contentChanges",
+                vec![Json::object(vec![("text", text.into())])].into(),
+            ),
+        ])
+    }
+
+    /// Runs a server on `messages` and returns what it sent back.
+    fn exchange(messages: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        serve(input)
+    }
+
+    /// Runs a server on the raw bytes `input` and returns what it sent back.
+    fn serve(input: Vec<u8>) -> Vec<Json> {
+        let output = SharedOutput::default();
+        LspServer::new(Box::new(Cursor::new(input)), Box::new(output.clone())).run();
+        let mut reader = Cursor::new(output.take().into_bytes());
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut reader).unwrap() {
+            replies.push(reply.unwrap());
+        }
+        replies
+    }
+
+    #[test]
+    fn language_features() {
+        let code = "var total = 0;\n\
+                    fun add(n) {\n\
+                    \x20 total = total + n;\n\
+                    }\n\
+                    add(\"😀\"); add(2);";
+        let open = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![("uri", URI.into()), ("text", code.into())]),
+        )]);
+        let replies = exchange(&[
+            request(1, "initialize", Json::object(vec![])),
+            notification("initialized", Json::object(vec![])),
+            notification("textDocument/didOpen", open),
+            request(2, "textDocument/definition", at(4, 12)),
+            request(3, "textDocument/references", at(0, 5)),
+            request(4, "textDocument/hover", at(2, 3)),
+            request(
+                5,
+                "textDocument/documentSymbol",
+                Json::object(vec![(
+                    "textDocument",
+                    Json::object(vec![("uri", URI.into())]),
+                )]),
+            ),
+            request(6, "textDocument/completion", at(4, 0)),
+            notification("textDocument/didChange", change("var = 1;\nprint 1")),
+            request(7, "textDocument/unknown", Json::object(vec![])),
+            request(8, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+        ]);
+
+        let result = |id: u32| -> String {
+            replies
+                .iter()
+                .find(|reply| reply.get("id").and_then(Json::as_u64) == Some(u64::from(id)))
+                .map(|reply| {
+                    reply
+                        .get("result")
+                        .or_else(|| reply.get("error"))
+                        .unwrap()
+                        .to_string()
+                })
+                .unwrap()
+        };
+        let range = |line: u32, start: u32, end: u32| {
+            format!(
+                r#"{{"start":{{"line":{0},"character":{1}}},"end":{{"line":{0},"character":{2}}}}}"#,
+                line, start, end
+            )
+        };
+        let diagnostics: Vec<String> = replies
+            .iter()
+            .filter(|reply| reply.get("method").is_some())
+            .map(|reply| {
+                reply
+                    .get("params")
+                    .unwrap()
+                    .get("diagnostics")
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+
+        assert!(result(1).contains(r#""definitionProvider":true"#));
+        assert_eq!(diagnostics[0], "[]");
+        // the emoji before the second `add` takes two UTF-16 code units
+        assert_eq!(
+            result(2),
+            format!(r#"{{"uri":"{}","range":{}}}"#, URI, range(1, 4, 7))
+        );
+        let references = result(3);
+        assert_eq!(references.matches(r#""uri""#).count(), 3);
+        assert!(references.contains(&range(2, 2, 7)));
+        assert!(references.contains(&range(2, 10, 15)));
+        assert_eq!(
+            result(4),
+            format!(
+                r#"{{"contents":{{"kind":"markdown","value":"```wendell\nvar total = 0;\n```\nvariable, declared on line 1"}},"range":{}}}"#,
+                range(2, 2, 7)
+            )
+        );
+        let symbols = result(5);
+        assert!(symbols.contains(r#""name":"total","detail":"var total = 0;","kind":13"#));
+        assert!(symbols.contains(r#""name":"add","detail":"fun add(n) {","kind":12"#));
+        assert!(!symbols.contains(r#""name":"n""#));
+        let completions = result(6);
+        for label in ["total", "add", "range", "while"].iter() {
+            assert!(completions.contains(&format!(r#""label":"{}""#, label)));
+        }
+        assert!(diagnostics[1].contains(r#""range":{"start":{"line":0,"character":0}"#));
+        assert!(diagnostics[1].contains(r#""line":1,"character":7"#));
+        assert!(result(7).contains("-32601"));
+        assert_eq!(result(8), "null");
+    }
+
+    #[test]
+    fn parse_error() {
+        let mut input = b"Content-Length: 5\r\n\r\n{\"id\"".to_vec();
+        write_message(&mut input, &request(1, "shutdown", Json::Null)).unwrap();
+        let replies = serve(input);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].get("id"), Some(&Json::Null));
+        assert!(replies[0]
+            .get("error")
+            .unwrap()
+            .to_string()
+            .contains("-32700"));
+        assert_eq!(replies[1].get("result"), Some(&Json::Null));
+    }
+}
@@ -0,0 +1,138 @@
+use ast::stmt::Stmt;
+use error::Error;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Finds and keeps track of the files pulled in with `import`. A module is
+/// looked up next to the file importing it, then in each search path.
+pub struct Modules {
+    search_paths: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+    loading: Vec<PathBuf>,
+}
+
+impl Modules {
+    pub fn add_search_path(&mut self, path: PathBuf) {
+        self.search_paths.push(path);
+    }
+
+    /// Resolves `name`, imported from the file `importer`, to the canonical
+    /// path of an existing file.
+    pub fn resolve(&self, name: &str, importer: Option<&Path>) -> Option<PathBuf> {
+        let base = match importer.and_then(|file| file.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return name.canonicalize().ok();
+        }
+        Some(base)
+            .into_iter()
+            .chain(self.search_paths.iter().cloned())
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+    }
+
+    /// The file being run, if any.
+    pub fn current(&self) -> Option<&Path> {
+        self.loading.last().map(PathBuf::as_path)
+    }
+
+    pub fn is_loaded(&self, path: &Path) -> bool {
+        self.loaded.contains(path)
+    }
+
+    /// The chain of imports leading back to `path`, if loading it would close
+    /// a cycle.
+    pub fn cycle(&self, path: &Path) -> Option<String> {
+        let start = self.loading.iter().position(|file| file == path)?;
+        let chain: Vec<String> = self.loading[start..]
+            .iter()
+            .chain(Some(&path.to_path_buf()))
+            .map(|file| file.display().to_string())
+            .collect();
+        Some(chain.join(" -> "))
+    }
+
+    /// Marks `path` as the file being run, until `leave` is called.
+    pub fn enter(&mut self, path: PathBuf) {
+        self.loading.push(path);
+    }
+
+    /// Finishes the file entered last. Only modules that ran without errors are
+    /// cached; a failed one is loaded again by the next import.
+    pub fn leave(&mut self, success: bool) {
+        if let Some(path) = self.loading.pop() {
+            if success {
+                self.loaded.insert(path);
+            }
+        }
+    }
+
+    /// Forgets every loaded module, keeping the search paths.
+    pub fn reset(&mut self) {
+        self.loaded.clear();
+    }
+}
+
+impl Default for Modules {
+    fn default() -> Self {
+        let search_paths = match env::var_os("WENDELL_PATH") {
+            Some(paths) => env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            search_paths,
+            loaded: HashSet::new(),
+            loading: Vec::new(),
+        }
+    }
+}
+
+/// Scans, parses and resolves a module, stopping at the first error.
+pub fn compile(code: String) -> Result<Vec<Stmt>, Error> {
+    let mut scanner = Scanner::new(code, 1);
+    scanner.scan_all_tokens()?;
+    let mut parser = Parser::new(scanner.tokens);
+    parser.parse()?;
+    Resolver::default().resolve(&parser.stmts)?;
+    Ok(parser.stmts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn resolve_and_cycle() {
+        let dir = env::temp_dir().join("wendell_module_test");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.aul"), "").unwrap();
+        fs::write(dir.join("lib/util.aul"), "").unwrap();
+        let main = dir.join("main.aul").canonicalize().unwrap();
+
+        let mut modules = Modules::default();
+        modules.enter(main.clone());
+        assert!(modules.resolve("util.aul", Some(&main)).is_none());
+        let lib_util = modules.resolve("util.aul", Some(&dir.join("lib/x.aul")));
+        assert_eq!(lib_util, dir.join("lib/util.aul").canonicalize().ok());
+        modules.add_search_path(dir.join("lib"));
+        let util = modules.resolve("util.aul", Some(&main)).unwrap();
+        assert_eq!(util, dir.join("lib/util.aul").canonicalize().unwrap());
+
+        modules.enter(util.clone());
+        let chain = modules.cycle(&main).unwrap();
+        assert_eq!(chain.matches(" -> ").count(), 2);
+        modules.leave(false);
+        assert!(!modules.is_loaded(&util));
+        modules.leave(true);
+        assert!(modules.is_loaded(&main));
+    }
+}
@@ -1,3 +1,4 @@
+use ast::arm::MatchArm;
 use ast::expr::*;
 use ast::stmt::*;
 use error::Error;
@@ -5,10 +6,18 @@ use scanner::token::TokenKind::*;
 use scanner::token::{Token, TokenKind};
 use std::mem::replace;
 
+/// How deep expressions and statements may nest. Parsing, resolving and
+/// running recurse once per level, so this guards the native stack.
+const MAX_NESTING: usize = 100;
+
 pub struct Parser {
     pub tokens: Vec<Token>,
     pub stmts: Vec<Stmt>,
     current: usize,
+    loop_depth: usize,
+    function_depth: usize,
+    nesting: usize,
+    repl: bool,
 }
 
 impl Parser {
@@ -17,12 +26,29 @@ impl Parser {
             tokens,
             current: 0,
             stmts: Vec::new(),
+            loop_depth: 0,
+            function_depth: 0,
+            nesting: 0,
+            repl: false,
+        }
+    }
+
+    /// A parser for REPL input, where the last expression statement may omit
+    /// its semicolon.
+    pub fn new_repl(tokens: Vec<Token>) -> Self {
+        Self {
+            repl: true,
+            ..Self::new(tokens)
         }
     }
 
     pub fn parse(&mut self) -> Result<(), Error> {
         while !self.is_eof() {
-            let decl = self.declaration()?;
+            let decl = if self.check(&Import) {
+                self.decl_import()?
+            } else {
+                self.declaration()?
+            };
             self.stmts.push(decl);
         }
 
@@ -37,7 +63,8 @@ impl Parser {
                 return;
             }
             match self.peek().kind {
-                Class | Fun | Var | For | If | While | Print | Return => {
+                Class | Fun | Var | Const | Import | For | If | While | Match | Print | Return
+                | Break | Continue | Throw | Try => {
                     return;
                 }
                 _ => (),
@@ -50,25 +77,59 @@ impl Parser {
         let token = self.peek();
         match token.kind {
             Var => self.decl_var(),
-            Fun => self.decl_fun("function"),
+            Const => self.decl_const(),
+            Fun if self.check_next(&Identifier("".to_string())) => self.decl_fun("function"),
             _ => self.statement(),
         }
     }
 
     fn statement(&mut self) -> Result<Stmt, Error> {
+        self.nested("Statement", Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, Error> {
         let token = self.peek();
         match token.kind {
             LeftBrace => self.stmt_block(),
             If => self.stmt_if(),
             While => self.stmt_while(),
             For => self.stmt_for(),
+            Match => self.stmt_match(),
+            Break => self.stmt_break(),
+            Continue => self.stmt_continue(),
+            Return => self.stmt_return(),
+            Throw => self.stmt_throw(),
+            Try => self.stmt_try(),
+            Import => self.error("Import is only allowed at the top level.".into()),
             Print => self.stmt_print(),
             _ => self.stmt_expression(),
         }
     }
 
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.expr_assignment()
+        self.nested("Expression", Self::expr_assignment)
+    }
+
+    /// Runs `parse` one nesting level deeper, failing past `MAX_NESTING`.
+    fn nested<T>(
+        &mut self,
+        what: &str,
+        parse: fn(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.nesting >= MAX_NESTING {
+            return self.too_deep(what);
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn too_deep<T>(&self, what: &str) -> Result<T, Error> {
+        self.error(format!(
+            "{} nested too deeply (more than {} levels).",
+            what, MAX_NESTING
+        ))
     }
 
     fn decl_var(&mut self) -> Result<Stmt, Error> {
@@ -84,6 +145,28 @@ impl Parser {
         Ok(Stmt::new_var(name, init))
     }
 
+    fn decl_const(&mut self) -> Result<Stmt, Error> {
+        self.advance(); // eat const token
+        let name = self.consume(&Identifier("".to_string()), "Expect constant name.")?;
+        self.consume(&Equal, "Expect '=' after constant name.")?;
+        let init = self.expression()?;
+        self.consume(&Semicolon, "Expect ';' after const statement.")?;
+
+        Ok(Stmt::new_const(name, init))
+    }
+
+    fn decl_import(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.advance(); // eat import token
+        let path = self.consume(&StringLiteral("".to_string()), "Expect module path.")?;
+        if self.peek().kind == Identifier("as".to_string()) {
+            return self
+                .error("Import aliases are not supported until modules have namespaces.".into());
+        }
+        self.consume(&Semicolon, "Expect ';' after import statement.")?;
+
+        Ok(Stmt::new_import(keyword, path))
+    }
+
     fn decl_fun(&mut self, kind: &str) -> Result<Stmt, Error> {
         self.advance(); // eat fun token
         let name = self.consume(
@@ -91,7 +174,13 @@ impl Parser {
             &format!("Expect {} name.", kind),
         )?;
         self.consume(&LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let parameters = self.parameters()?;
+        let body = self.function_body(kind)?;
+
+        Ok(Stmt::new_function(name, parameters, Box::new(body)))
+    }
 
+    fn parameters(&mut self) -> Result<Vec<Token>, Error> {
         let mut parameters: Vec<Token> = Vec::new();
         if !self.check(&RightParen) {
             while {
@@ -101,13 +190,21 @@ impl Parser {
             } {}
         }
         self.consume(&RightParen, "Expect ')' after parameters.")?;
+        Ok(parameters)
+    }
 
+    fn function_body(&mut self, kind: &str) -> Result<Stmt, Error> {
         if !self.check(&LeftBrace) {
             self.error::<()>(format!("Expect '{{' before {} body.", kind))?;
         }
 
-        let body = self.stmt_block()?;
-        Ok(Stmt::new_function(name, parameters, Box::new(body)))
+        // loops do not extend into the function body
+        let loop_depth = replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.stmt_block();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        body
     }
 
     fn stmt_block(&mut self) -> Result<Stmt, Error> {
@@ -149,15 +246,24 @@ impl Parser {
         let condition = self.expression()?;
         self.consume(&RightParen, "Expect ')' after while condition.")?;
 
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
-        Ok(Stmt::new_while(token.line, condition, Box::new(body)))
+        Ok(Stmt::new_while(
+            token.line,
+            condition,
+            Box::new(body),
+            Box::new(Stmt::Empty),
+        ))
     }
 
     fn stmt_for(&mut self) -> Result<Stmt, Error> {
         let token = self.advance(); // eat 'for' token
 
-        self.consume(&LeftParen, "Expect '(' after 'if'.")?;
+        self.consume(&LeftParen, "Expect '(' after 'for'.")?;
+
+        if self.check(&Identifier("".to_string())) && self.check_next(&In) {
+            return self.stmt_forin();
+        }
 
         let initializer = if self.match_token(&[Semicolon]) {
             Stmt::Empty
@@ -181,20 +287,166 @@ impl Parser {
         };
         self.consume(&RightParen, "Expect ')' after for clauses.")?;
 
-        let body = self.statement()?;
+        let body = self.loop_body()?;
         Ok(Stmt::new_block(vec![
             initializer,
-            Stmt::new_while(
-                token.line,
-                condition,
-                Box::new(Stmt::new_block(vec![body, increment])),
-            ),
+            Stmt::new_while(token.line, condition, Box::new(body), Box::new(increment)),
         ]))
     }
 
+    fn stmt_forin(&mut self) -> Result<Stmt, Error> {
+        let name = self.advance(); // eat loop variable
+        self.advance(); // eat 'in' token
+        let iterable = self.expression()?;
+        self.consume(&RightParen, "Expect ')' after for-in clause.")?;
+
+        let body = self.loop_body()?;
+        Ok(Stmt::new_forin(name, iterable, Box::new(body)))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, Error> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn stmt_match(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.advance(); // eat 'match' token
+        self.consume(&LeftParen, "Expect '(' after 'match'.")?;
+        let value = self.expression()?;
+        self.consume(&RightParen, "Expect ')' after match value.")?;
+        self.consume(&LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms: Vec<MatchArm> = Vec::new();
+        while !self.check(&RightBrace) && !self.is_eof() {
+            if arms.last().is_some_and(MatchArm::is_default) {
+                return self.error("Unreachable match arm after default arm.".into());
+            }
+            arms.push(self.match_arm()?);
+        }
+
+        self.consume(&RightBrace, "Expect '}' after match arms.")?;
+        Ok(Stmt::new_match(keyword, value, arms))
+    }
+
+    fn match_arm(&mut self) -> Result<MatchArm, Error> {
+        let mut patterns: Vec<Expr> = Vec::new();
+        if self.peek().kind == Identifier("_".to_string()) {
+            self.advance(); // eat '_' token
+        } else {
+            while {
+                patterns.push(self.match_pattern()?);
+                self.match_token(&[Comma])
+            } {}
+        }
+
+        let guard = if self.match_token(&[If]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&Arrow, "Expect '=>' after match pattern.")?;
+
+        let body = self.statement()?;
+        Ok(MatchArm::new(patterns, guard, body))
+    }
+
+    fn match_pattern(&mut self) -> Result<Expr, Error> {
+        let token = self.advance();
+        match token.kind {
+            False | True | Nil | NumberLiteral(_) | StringLiteral(_) => {
+                Ok(Expr::new_literal(token))
+            }
+            Minus if self.check(&NumberLiteral("".to_string())) => {
+                let number = Expr::new_literal(self.advance());
+                Ok(Expr::new_unary(token, Box::new(number)))
+            }
+            _ => self.error(format!("Expect literal pattern, found '{}'.", token)),
+        }
+    }
+
+    fn stmt_break(&mut self) -> Result<Stmt, Error> {
+        if self.loop_depth == 0 {
+            return self.error("Can't use 'break' outside of a loop.".into());
+        }
+        let keyword = self.advance(); // eat break token
+        self.consume(&Semicolon, "Expect ';' after 'break'.")?;
+        Ok(Stmt::new_break(keyword))
+    }
+
+    fn stmt_continue(&mut self) -> Result<Stmt, Error> {
+        if self.loop_depth == 0 {
+            return self.error("Can't use 'continue' outside of a loop.".into());
+        }
+        let keyword = self.advance(); // eat continue token
+        self.consume(&Semicolon, "Expect ';' after 'continue'.")?;
+        Ok(Stmt::new_continue(keyword))
+    }
+
+    fn stmt_return(&mut self) -> Result<Stmt, Error> {
+        if self.function_depth == 0 {
+            return self.error("Can't return from top-level code.".into());
+        }
+        let keyword = self.advance(); // eat return token
+        let value = if self.check(&Semicolon) {
+            Expr::new_literal(Token::new(Nil, keyword.line))
+        } else {
+            self.expression()?
+        };
+        self.consume(&Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::new_return(keyword, value))
+    }
+
+    fn stmt_throw(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.advance(); // eat 'throw' token
+        let value = self.expression()?;
+        self.consume(&Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::new_throw(keyword, value))
+    }
+
+    fn stmt_try(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.advance(); // eat 'try' token
+        let body = self.clause_block("try")?;
+
+        let (catch_name, catch_body) = if self.match_token(&[Catch]) {
+            self.consume(&LeftParen, "Expect '(' after 'catch'.")?;
+            let name = self.consume(&Identifier("".to_string()), "Expect error name.")?;
+            self.consume(&RightParen, "Expect ')' after error name.")?;
+            (Some(name), self.clause_block("catch")?)
+        } else {
+            (None, Stmt::Empty)
+        };
+
+        let finally_body = if self.match_token(&[Finally]) {
+            self.clause_block("finally")?
+        } else if catch_name.is_none() {
+            return self.error("Expect 'catch' or 'finally' after try block.".into());
+        } else {
+            Stmt::Empty
+        };
+
+        Ok(Stmt::new_try(
+            keyword,
+            Box::new(body),
+            catch_name,
+            Box::new(catch_body),
+            Box::new(finally_body),
+        ))
+    }
+
+    fn clause_block(&mut self, clause: &str) -> Result<Stmt, Error> {
+        if !self.check(&LeftBrace) {
+            return self.error(format!("Expect '{{' after '{}'.", clause));
+        }
+        self.stmt_block()
+    }
+
     fn stmt_expression(&mut self) -> Result<Stmt, Error> {
         let expr = self.expression()?;
-        self.consume(&Semicolon, "Expect ';' after expression.")?;
+        if !(self.repl && self.is_eof()) {
+            self.consume(&Semicolon, "Expect ';' after expression.")?;
+        }
         Ok(Stmt::new_expression(expr))
     }
 
@@ -227,81 +479,76 @@ impl Parser {
     }
 
     fn expr_or(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_and()?;
-
-        while self.match_token(&[Or]) {
-            let operator = self.previous();
-            let right = self.expr_comparison()?;
-            Self::extend_binary(&mut expr, operator, right);
-        }
-
-        Ok(expr)
+        self.binary_chain(&[Or], Self::expr_and, Self::expr_comparison)
     }
 
     fn expr_and(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_equality()?;
-
-        while self.match_token(&[And]) {
-            let operator = self.previous();
-            let right = self.expr_comparison()?;
-            Self::extend_binary(&mut expr, operator, right);
-        }
-
-        Ok(expr)
+        self.binary_chain(&[And], Self::expr_equality, Self::expr_comparison)
     }
 
     fn expr_equality(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_comparison()?;
-
-        while self.match_token(&[BangEqual, EqualEqual]) {
-            let operator = self.previous();
-            let right = self.expr_comparison()?;
-            Self::extend_binary(&mut expr, operator, right);
-        }
-
-        Ok(expr)
+        self.binary_chain(
+            &[BangEqual, EqualEqual],
+            Self::expr_comparison,
+            Self::expr_comparison,
+        )
     }
 
     fn expr_comparison(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_addition()?;
-
-        while self.match_token(&[Greater, GreaterEqual, Less, LessEqual]) {
-            let operator = self.previous();
-            let right = self.expr_addition()?;
-            Self::extend_binary(&mut expr, operator, right);
-        }
-
-        Ok(expr)
+        self.binary_chain(
+            &[Greater, GreaterEqual, Less, LessEqual],
+            Self::expr_addition,
+            Self::expr_addition,
+        )
     }
 
     fn expr_addition(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_multiplication()?;
-
-        while self.match_token(&[Plus, Minus]) {
-            let operator = self.previous();
-            let right = self.expr_multiplication()?;
-            Self::extend_binary(&mut expr, operator, right);
-        }
-
-        Ok(expr)
+        self.binary_chain(
+            &[Plus, Minus],
+            Self::expr_multiplication,
+            Self::expr_multiplication,
+        )
     }
 
     fn expr_multiplication(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.expr_unary()?;
-
-        while self.match_token(&[Star, Slash]) {
+        self.binary_chain(&[Star, Slash], Self::expr_unary, Self::expr_unary)
+    }
+
+    /// Parses `first`, then joins each `operand` after one of `operators` to
+    /// the left. Every operator makes the tree one level deeper, so a chain
+    /// counts against `MAX_NESTING` like parentheses do.
+    fn binary_chain(
+        &mut self,
+        operators: &[TokenKind],
+        first: fn(&mut Self) -> Result<Expr, Error>,
+        operand: fn(&mut Self) -> Result<Expr, Error>,
+    ) -> Result<Expr, Error> {
+        let mut expr = first(self)?;
+        let nesting = self.nesting;
+        let mut result = Ok(());
+        while self.match_token(operators) {
+            if self.nesting >= MAX_NESTING {
+                result = self.too_deep("Expression");
+                break;
+            }
+            self.nesting += 1;
             let operator = self.previous();
-            let right = self.expr_unary()?;
-            Self::extend_binary(&mut expr, operator, right);
+            match operand(self) {
+                Ok(right) => Self::extend_binary(&mut expr, operator, right),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
         }
-
-        Ok(expr)
+        self.nesting = nesting;
+        result.map(|_| expr)
     }
 
     fn expr_unary(&mut self) -> Result<Expr, Error> {
         if self.match_token(&[Bang, Minus]) {
             let operator = self.previous();
-            let right = self.expr_unary()?;
+            let right = self.nested("Expression", Self::expr_unary)?;
             return Ok(Expr::new_unary(operator, Box::new(right)));
         }
 
@@ -314,6 +561,12 @@ impl Parser {
         loop {
             if self.match_token(&[LeftParen]) {
                 self.finish_expr_call(&mut expr)?;
+            } else if self.match_token(&[Dot]) {
+                let name = self.consume(
+                    &Identifier("".to_string()),
+                    "Expect property name after '.'.",
+                )?;
+                expr = Expr::new_get(Box::new(expr), name);
             } else {
                 break;
             }
@@ -323,12 +576,17 @@ impl Parser {
     }
 
     fn expr_primary(&mut self) -> Result<Expr, Error> {
+        if self.check(&LeftParen) && self.is_arrow_lambda() {
+            return self.expr_arrow();
+        }
+
         let token = self.advance();
         match token.kind {
             False | True | Nil | NumberLiteral(_) | StringLiteral(_) => {
                 Ok(Expr::new_literal(token))
             }
             Identifier(_) => Ok(Expr::new_variable(token)),
+            Fun => self.expr_lambda(token),
             LeftParen => {
                 let expr = self.expression()?;
                 self.consume(&RightParen, "Expect ')' after expression.")?;
@@ -338,6 +596,47 @@ impl Parser {
         }
     }
 
+    fn expr_lambda(&mut self, keyword: Token) -> Result<Expr, Error> {
+        self.consume(&LeftParen, "Expect '(' after 'fun'.")?;
+        let parameters = self.parameters()?;
+        let body = self.function_body("function")?;
+        Ok(Expr::new_lambda(keyword, parameters, Box::new(body)))
+    }
+
+    fn expr_arrow(&mut self) -> Result<Expr, Error> {
+        let keyword = self.advance(); // eat '(' token
+        let parameters = self.parameters()?;
+        let arrow = self.consume(&Arrow, "Expect '=>' after parameters.")?;
+
+        let loop_depth = replace(&mut self.loop_depth, 0);
+        let value = self.expression();
+        self.loop_depth = loop_depth;
+
+        let body = Stmt::new_return(arrow, value?);
+        Ok(Expr::new_lambda(keyword, parameters, Box::new(body)))
+    }
+
+    // Looks past a parenthesized identifier list for '=>'.
+    fn is_arrow_lambda(&self) -> bool {
+        let mut i = self.current + 1;
+        let mut expect_name = true;
+        while let Some(token) = self.tokens.get(i) {
+            match token.kind {
+                Identifier(_) if expect_name => expect_name = false,
+                Comma if !expect_name => expect_name = true,
+                RightParen => {
+                    return match self.tokens.get(i + 1) {
+                        Some(next) => next.kind == Arrow,
+                        None => false,
+                    }
+                }
+                _ => return false,
+            }
+            i += 1;
+        }
+        false
+    }
+
     fn finish_expr_call(&mut self, callee: &mut Expr) -> Result<(), Error> {
         let mut arguments: Vec<Expr> = Vec::new();
         if !self.check(&RightParen) {
@@ -383,6 +682,13 @@ impl Parser {
         self.peek().kind.match_kind(kind)
     }
 
+    fn check_next(&self, kind: &TokenKind) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.kind.match_kind(kind),
+            None => false,
+        }
+    }
+
     fn peek(&self) -> Token {
         self.tokens[self.current].clone()
     }
@@ -405,9 +711,62 @@ impl Parser {
     }
 
     fn error<T>(&self, msg: String) -> Result<T, Error> {
-        Err(Error {
-            line: self.peek().line,
-            msg,
-        })
+        Err(Error::new(self.peek().line, msg))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use module::compile;
+
+    fn parse(code: &str) -> Result<(), Error> {
+        compile(code.into()).map(|_| ())
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        assert!(parse("while (true) { break; }").is_ok());
+        assert!(parse("break;").is_err());
+        assert!(parse("if (true) { continue; }").is_err());
+        assert!(parse("while (true) { fun f() { break; } }").is_err());
+    }
+
+    #[test]
+    fn return_outside_function() {
+        assert!(parse("fun f() { return 1; }").is_ok());
+        assert!(parse("var f = (a, b) => a + b;").is_ok());
+        assert!(parse("return 1;").is_err());
+        assert!(parse("while (true) { var f = fun () { break; }; }").is_err());
+    }
+
+    #[test]
+    fn try_clauses() {
+        assert!(parse("try { } catch (e) { }").is_ok());
+        assert!(parse("try { } finally { }").is_ok());
+        assert!(parse("try { } catch (e) { } finally { }").is_ok());
+        assert!(parse("try { }").is_err());
+        assert!(parse("try { } catch e { }").is_err());
+        assert!(parse("try print 1;").is_err());
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nest = |open: &str, close: &str, n: usize| {
+            format!("print {}1{};", open.repeat(n), close.repeat(n))
+        };
+        assert!(parse(&nest("(", ")", 50)).is_ok());
+        let e = parse(&nest("(", ")", 1000)).err().unwrap();
+        assert_eq!(
+            e.msg,
+            "Expression nested too deeply (more than 100 levels)."
+        );
+        assert!(parse(&nest("-", "", 1000)).is_err());
+        // a chain of operators nests as deeply as parentheses
+        let chain = |n: usize| format!("print {};", vec!["1"; n].join(" + "));
+        assert!(parse(&chain(50)).is_ok());
+        assert!(parse(&chain(20000)).is_err());
+        assert!(parse(&format!("print {};", vec!["a"; 20000].join(" or "))).is_err());
+        assert!(parse(&"{".repeat(1000)).is_err());
     }
 }
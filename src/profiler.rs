@@ -0,0 +1,226 @@
+use ast::stmt::Stmt;
+use error::Error;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use worker::{Hook, Worker};
+
+/// How many of the most run lines the report lists.
+const REPORT_LINES: usize = 20;
+
+/// Shown for the code outside any function.
+const MAIN: &str = "<main>";
+
+#[derive(Default)]
+struct FunctionStats {
+    calls: u64,
+    // time from call to return, counting a recursive function once
+    inclusive: Duration,
+    // inclusive time minus the time spent in other calls
+    exclusive: Duration,
+}
+
+struct Frame {
+    name: String,
+    start: Instant,
+    // time spent in the calls it made
+    children: Duration,
+}
+
+/// Times every function call and counts the statements run on each line.
+#[derive(Default)]
+pub struct Profiler {
+    functions: HashMap<String, FunctionStats>,
+    lines: HashMap<(Option<PathBuf>, u32), u64>,
+    frames: Vec<Frame>,
+    // exclusive time of each call stack, names joined with ';'
+    stacks: HashMap<String, Duration>,
+}
+
+impl Profiler {
+    /// Starts timing a run of the main script.
+    pub fn begin_run(&mut self) {
+        self.push(MAIN.to_string());
+    }
+
+    /// Stops timing the run started with `begin_run`.
+    pub fn end_run(&mut self) {
+        while !self.frames.is_empty() {
+            self.pop();
+        }
+    }
+
+    fn push(&mut self, name: String) {
+        self.frames.push(Frame {
+            name,
+            start: Instant::now(),
+            children: Duration::default(),
+        });
+    }
+
+    fn pop(&mut self) {
+        let path = self
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect::<Vec<&str>>()
+            .join(";");
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let exclusive = elapsed.checked_sub(frame.children).unwrap_or_default();
+        *self.stacks.entry(path).or_default() += exclusive;
+
+        let parent = match self.frames.last_mut() {
+            Some(parent) => parent,
+            // the main script is not a function
+            None => return,
+        };
+        parent.children += elapsed;
+        let recursive = self.frames.iter().any(|other| other.name == frame.name);
+        let stats = self.functions.entry(frame.name).or_default();
+        stats.calls += 1;
+        stats.exclusive += exclusive;
+        if !recursive {
+            stats.inclusive += elapsed;
+        }
+    }
+
+    /// Functions by exclusive time, then the most run lines.
+    pub fn report(&self) -> String {
+        let mut functions: Vec<(&String, &FunctionStats)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        let mut report = String::new();
+        writeln!(
+            report,
+            "{:<24} {:>10} {:>14} {:>14}",
+            "function", "calls", "inclusive ms", "exclusive ms"
+        )
+        .ok();
+        for (name, stats) in functions {
+            writeln!(
+                report,
+                "{:<24} {:>10} {:>14.3} {:>14.3}",
+                name,
+                stats.calls,
+                millis(stats.inclusive),
+                millis(stats.exclusive)
+            )
+            .ok();
+        }
+
+        let mut lines: Vec<(String, u64)> = self
+            .lines
+            .iter()
+            .map(|((file, line), hits)| {
+                let file = file
+                    .as_ref()
+                    .and_then(|file| file.file_name())
+                    .map_or("line ".to_string(), |name| {
+                        format!("{}:", name.to_string_lossy())
+                    });
+                (format!("{}{}", file, line), *hits)
+            })
+            .collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(report, "\n{:<24} {:>10}", "line", "hits").ok();
+        for (line, hits) in lines.iter().take(REPORT_LINES) {
+            writeln!(report, "{:<24} {:>10}", line, hits).ok();
+        }
+        report
+    }
+
+    /// The exclusive time of each call stack in microseconds, one
+    /// `main;f;g 1234` line per stack, as read by flame graph tools.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut stacks: Vec<(&String, u128)> = self
+            .stacks
+            .iter()
+            .map(|(path, time)| (path, time.as_micros()))
+            .filter(|(_, micros)| *micros > 0)
+            .collect();
+        stacks.sort();
+        stacks
+            .iter()
+            .map(|(path, micros)| format!("{} {}\n", path, micros))
+            .collect()
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Hook for Profiler {
+    fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error> {
+        // a block counts through its statements
+        if let Stmt::Block { .. } = stmt {
+            return Ok(());
+        }
+        if let Some(line) = stmt.line() {
+            let file = worker.current_file().map(|file| file.to_path_buf());
+            *self.lines.entry((file, line)).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn before_call(&mut self, name: &str) {
+        self.push(name.to_string());
+    }
+
+    fn after_call(&mut self) {
+        self.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use api::load_std_api;
+    use module::compile;
+    use stack::Stack;
+
+    #[test]
+    fn calls_and_lines() {
+        let code = "fun fib(n) {\n\
+                    if (n < 2) return n;\n\
+                    return fib(n - 1) + fib(n - 2);\n\
+                    }\n\
+                    var sq = (x) => x * x;\n\
+                    for (i in range(3)) sq(fib(i));";
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+        load_std_api(&mut stack).unwrap();
+        stack.push();
+
+        let mut profiler = Profiler::default();
+        profiler.begin_run();
+        Worker::new(&mut stack)
+            .with_hook(&mut profiler)
+            .run(&stmts)
+            .unwrap();
+        profiler.end_run();
+
+        let calls = |name: &str| profiler.functions[name].calls;
+        // fib(0) + fib(1) + fib(2), which calls fib(1) and fib(0)
+        assert_eq!(calls("fib"), 5);
+        assert_eq!(calls("sq"), 3);
+        assert_eq!(calls("range"), 1);
+        assert!(!profiler.functions.contains_key(MAIN));
+        for stats in profiler.functions.values() {
+            assert!(stats.inclusive >= stats.exclusive);
+        }
+        // five ifs, four of them returning
+        assert_eq!(profiler.lines[&(None, 2)], 9);
+        assert_eq!(profiler.lines[&(None, 3)], 1);
+
+        assert!(profiler.stacks.contains_key("<main>;fib;fib"));
+        assert!(profiler.stacks.contains_key("<main>;sq"));
+        let report = profiler.report();
+        assert!(report.starts_with("function"));
+        assert!(report.contains("\nline 2 "));
+    }
+}
@@ -0,0 +1,306 @@
+use ast::arm::MatchArm;
+use ast::expr::*;
+use ast::stmt::*;
+use error::Error;
+use scanner::token::{Token, TokenKind};
+use std::collections::HashMap;
+
+/// Checks bindings over the lexical scopes of a parsed program before it runs.
+/// Each scope maps a name to the declaration line of a constant, or `None`
+/// for a plain variable.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, Option<u32>>>,
+}
+
+impl Resolver {
+    pub fn resolve(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        for stmt in stmts.iter() {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        stmt.accept(self)
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), Error> {
+        expr.accept(self)
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token, const_line: Option<u32>) -> Result<(), Error> {
+        let var_name = Self::name_of(name);
+        let scope = self.scopes.last_mut().expect("Resolver has no scope");
+        if let Some(Some(line)) = scope.get(var_name) {
+            return Err(Error::new(
+                name.line,
+                format!(
+                    "Cannot redefine constant '{}' declared on line {}.",
+                    var_name, line
+                ),
+            ));
+        }
+        scope.insert(var_name.to_string(), const_line);
+        Ok(())
+    }
+
+    fn check_assign(&self, name: &Token) -> Result<(), Error> {
+        let var_name = Self::name_of(name);
+        for scope in self.scopes.iter().rev() {
+            match scope.get(var_name) {
+                Some(Some(line)) => {
+                    return Err(Error::new(
+                        name.line,
+                        format!(
+                            "Cannot assign to constant '{}' declared on line {}.",
+                            var_name, line
+                        ),
+                    ))
+                }
+                Some(None) => return Ok(()),
+                None => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_function(&mut self, params: &[Token], body: &Stmt) -> Result<(), Error> {
+        self.begin_scope();
+        for param in params {
+            self.declare(param, None)?;
+        }
+        self.resolve_stmt(body)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn name_of(name: &Token) -> &str {
+        match name.kind {
+            TokenKind::Identifier(ref var_name) => var_name,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+        }
+    }
+}
+
+impl ExprVisitor<Result<(), Error>> for Resolver {
+    fn visit_assign(&mut self, name: &Token, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value)?;
+        self.check_assign(name)
+    }
+
+    fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> Result<(), Error> {
+        self.resolve_expr(left)?;
+        self.resolve_expr(right)
+    }
+
+    fn visit_grouping(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.resolve_expr(expression)
+    }
+
+    fn visit_literal(&mut self, _value: &Token) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_unary(&mut self, _operator: &Token, right: &Expr) -> Result<(), Error> {
+        self.resolve_expr(right)
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        _paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<(), Error> {
+        self.resolve_expr(callee)?;
+        for arg in arguments {
+            self.resolve_expr(arg)?;
+        }
+        Ok(())
+    }
+
+    fn visit_variable(&mut self, _name: &Token) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_lambda(
+        &mut self,
+        _keyword: &Token,
+        params: &[Token],
+        body: &Stmt,
+    ) -> Result<(), Error> {
+        self.resolve_function(params, body)
+    }
+
+    fn visit_get(&mut self, object: &Expr, _name: &Token) -> Result<(), Error> {
+        self.resolve_expr(object)
+    }
+
+    fn visit_empty_expr(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl StmtVisitor<Result<(), Error>> for Resolver {
+    fn visit_var(&mut self, name: &Token, initializer: &Expr) -> Result<(), Error> {
+        self.resolve_expr(initializer)?;
+        self.declare(name, None)
+    }
+
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) -> Result<(), Error> {
+        self.resolve_expr(initializer)?;
+        self.declare(name, Some(name.line))
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, _path: &Token) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        self.begin_scope();
+        self.resolve(statements)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_if(
+        &mut self,
+        _line_number: &u32,
+        condition: &Expr,
+        then_block: &Stmt,
+        else_block: &Stmt,
+    ) -> Result<(), Error> {
+        self.resolve_expr(condition)?;
+        self.resolve_stmt(then_block)?;
+        self.resolve_stmt(else_block)
+    }
+
+    fn visit_while(
+        &mut self,
+        _line_number: &u32,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Stmt,
+    ) -> Result<(), Error> {
+        self.resolve_expr(condition)?;
+        self.resolve_stmt(body)?;
+        self.resolve_stmt(increment)
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> Result<(), Error> {
+        self.resolve_expr(iterable)?;
+        self.begin_scope();
+        self.declare(name, None)?;
+        self.resolve_stmt(body)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_match(
+        &mut self,
+        _keyword: &Token,
+        value: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<(), Error> {
+        self.resolve_expr(value)?;
+        for arm in arms {
+            if let Some(ref guard) = arm.guard {
+                self.resolve_expr(guard)?;
+            }
+            self.resolve_stmt(&arm.body)?;
+        }
+        Ok(())
+    }
+
+    fn visit_break(&mut self, _keyword: &Token) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_continue(&mut self, _keyword: &Token) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_function(&mut self, name: &Token, params: &[Token], body: &Stmt) -> Result<(), Error> {
+        self.declare(name, None)?;
+        self.resolve_function(params, body)
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value)
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) -> Result<(), Error> {
+        self.resolve_expr(value)
+    }
+
+    fn visit_try(
+        &mut self,
+        _keyword: &Token,
+        body: &Stmt,
+        catch_name: &Option<Token>,
+        catch_body: &Stmt,
+        finally_body: &Stmt,
+    ) -> Result<(), Error> {
+        self.resolve_stmt(body)?;
+        if let Some(name) = catch_name {
+            self.begin_scope();
+            self.declare(name, None)?;
+            self.resolve_stmt(catch_body)?;
+            self.end_scope();
+        }
+        self.resolve_stmt(finally_body)
+    }
+
+    fn visit_expression(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.resolve_expr(expression)
+    }
+
+    fn visit_print(&mut self, expression: &Expr) -> Result<(), Error> {
+        self.resolve_expr(expression)
+    }
+
+    fn visit_empty_stmt(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use module::compile;
+
+    fn resolve(code: &str) -> Result<(), Error> {
+        compile(code.into()).map(|_| ())
+    }
+
+    #[test]
+    fn const_assignment() {
+        assert!(resolve("const a = 1; { var a = 2; a = 3; }").is_ok());
+        assert!(resolve("const a = 1;\nvar a = 2;").is_err());
+
+        let e = resolve("const limit = 1;\n{ { limit = 2; } }").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(
+            e.msg,
+            "Cannot assign to constant 'limit' declared on line 1."
+        );
+
+        let e = resolve("const f = 1;\nfun g() { if (true) f = 2; }").unwrap_err();
+        assert_eq!(e.line, 2);
+    }
+}
@@ -4,12 +4,43 @@ use self::token::TokenKind::*;
 use self::token::{Token, TokenKind};
 use error::Error;
 
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("and", And),
+    ("break", Break),
+    ("catch", Catch),
+    ("class", Class),
+    ("const", Const),
+    ("continue", Continue),
+    ("else", Else),
+    ("false", False),
+    ("finally", Finally),
+    ("for", For),
+    ("fun", Fun),
+    ("if", If),
+    ("import", Import),
+    ("in", In),
+    ("match", Match),
+    ("nil", Nil),
+    ("or", Or),
+    ("print", Print),
+    ("return", Return),
+    ("super", Super),
+    ("this", This),
+    ("throw", Throw),
+    ("true", True),
+    ("try", Try),
+    ("var", Var),
+    ("while", While),
+];
+
 pub struct Scanner {
     pub source: Vec<char>,
     pub tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: u32,
+    // where the current line starts in `source`
+    line_start: usize,
 }
 
 impl Scanner {
@@ -20,6 +51,7 @@ impl Scanner {
             start: 0,
             current: 0,
             line: start_line,
+            line_start: 0,
         }
     }
 
@@ -58,6 +90,8 @@ impl Scanner {
             '=' => {
                 let kind = if self.match_char('=') {
                     EqualEqual
+                } else if self.match_char('>') {
+                    Arrow
                 } else {
                     Equal
                 };
@@ -91,7 +125,10 @@ impl Scanner {
             ' ' => {}
             '\r' => {}
             '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => {
+                self.line += 1;
+                self.line_start = self.current;
+            }
             '"' => self.add_string_literal()?,
             x if Self::is_digit(x) => self.add_number_literal(),
             x if Self::is_alpha(x) => self.add_identifier(),
@@ -104,6 +141,7 @@ impl Scanner {
         while self.peek() != '"' && !self.is_eof() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
@@ -157,28 +195,18 @@ impl Scanner {
             lexeme_slice.iter().collect()
         };
 
-        let kind = match &lexeme as &str {
-            "and" => And,
-            "class" => Class,
-            "else" => Else,
-            "false" => False,
-            "for" => For,
-            "fun" => Fun,
-            "if" => If,
-            "nil" => Nil,
-            "or" => Or,
-            "print" => Print,
-            "return" => Return,
-            "super" => Super,
-            "this" => This,
-            "true" => True,
-            "var" => Var,
-            "while" => While,
-            _ => Identifier(lexeme),
+        let kind = match KEYWORDS.iter().find(|(keyword, _)| *keyword == lexeme) {
+            Some((_, kind)) => kind.clone(),
+            None => Identifier(lexeme),
         };
         self.add_token(kind);
     }
 
+    /// Whether the last error came from a string still open at the end of input.
+    pub fn in_unterminated_string(&self) -> bool {
+        self.is_eof() && self.source.get(self.start) == Some(&'"')
+    }
+
     fn is_eof(&self) -> bool {
         self.current >= self.source.len()
     }
@@ -215,14 +243,15 @@ impl Scanner {
     }
 
     fn add_token(&mut self, kind: TokenKind) {
-        self.tokens.push(Token::new(kind, self.line));
+        self.tokens.push(Token {
+            kind,
+            line: self.line,
+            column: self.start.saturating_sub(self.line_start) as u32,
+        });
     }
 
     fn error(&self, msg: String) -> Result<(), Error> {
-        Err(Error {
-            line: self.line,
-            msg,
-        })
+        Err(Error::new(self.line, msg))
     }
 
     fn is_digit(c: char) -> bool {
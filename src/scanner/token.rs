@@ -17,6 +17,7 @@ pub enum TokenKind {
     Star,
 
     // One or two character tokens
+    Arrow,
     Bang,
     BangEqual,
     Equal,
@@ -33,19 +34,29 @@ pub enum TokenKind {
 
     // Keywords
     And,
+    Break,
+    Catch,
     Class,
+    Const,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -57,11 +68,18 @@ pub enum TokenKind {
 pub struct Token {
     pub kind: TokenKind,
     pub line: u32,
+    /// Characters between the start of the line and the token, or 0 for
+    /// tokens that do not come from source code.
+    pub column: u32,
 }
 
 impl Token {
     pub fn new(kind: TokenKind, line: u32) -> Self {
-        Self { kind, line }
+        Self {
+            kind,
+            line,
+            column: 0,
+        }
     }
 }
 
@@ -1,8 +1,13 @@
 use error::Error;
 use function::callable::Callable;
 use scanner::token::*;
+use std::cmp::max;
 use std::collections::HashMap;
-use std::mem::replace;
+use std::fmt;
+use std::mem::{replace, size_of};
+
+/// Bytes counted for a scope itself, on top of its bindings.
+const SCOPE_SIZE: usize = size_of::<Stack>();
 
 #[derive(Debug, Clone)]
 pub enum Instance {
@@ -11,42 +16,159 @@ pub enum Instance {
     String(String),
     Bool(bool),
     Function(Box<dyn Callable>),
+    /// A runtime error caught by `catch`.
+    Error {
+        message: String,
+        line: u32,
+    },
+}
+
+impl Instance {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Instance::Nil => "Nil",
+            Instance::Number(_) => "Number",
+            Instance::String(_) => "String",
+            Instance::Bool(_) => "Bool",
+            Instance::Function(_) => "Function",
+            Instance::Error { .. } => "Error",
+        }
+    }
+
+    /// Approximate bytes held by the value, including its own slot.
+    pub fn size(&self) -> usize {
+        size_of::<Instance>()
+            + match self {
+                Instance::String(s) => s.len(),
+                Instance::Function(fun) => fun.heap_size(),
+                Instance::Error { message, .. } => message.len(),
+                _ => 0,
+            }
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instance::String(s) => write!(f, "{}", s),
+            Instance::Number(n) => write!(f, "{}", n),
+            Instance::Bool(b) => write!(f, "{}", b),
+            Instance::Function(_) => write!(f, "function"),
+            Instance::Error { message, .. } => write!(f, "{}", message),
+            Instance::Nil => write!(f, "nil"),
+        }
+    }
 }
 
 pub struct Stack {
     pub height: usize,
     values: HashMap<String, Instance>,
+    consts: HashMap<String, u32>,
     next: Link,
+    // bytes held by this scope
+    scope_bytes: usize,
+    // bytes held by the whole chain, kept up to date on the innermost scope
+    used: usize,
+    peak: usize,
+    max_memory: Option<usize>,
 }
 
 type Link = Option<Box<Stack>>;
 
 impl Stack {
     pub fn push(&mut self) {
-        let height = self.height;
-        let old_self = replace(self, Self::raw_new(height + 1));
+        let mut scope = Self::raw_new(self.height + 1);
+        scope.used = self.used + SCOPE_SIZE;
+        scope.peak = max(self.peak, scope.used);
+        scope.max_memory = self.max_memory;
+        let old_self = replace(self, scope);
         self.next = Some(Box::new(old_self));
     }
 
     pub fn pop(&mut self) {
-        let new_self = match replace(&mut self.next, None) {
+        let new_self = match self.next.take() {
             Some(link) => link,
             None => panic!("Cannot pop global scope"),
         };
+        let used = self.used - self.scope_bytes;
+        let (peak, max_memory) = (self.peak, self.max_memory);
         *self = *new_self;
+        self.used = used;
+        self.peak = peak;
+        self.max_memory = max_memory;
+    }
+
+    /// Approximate bytes held by all scopes and their values.
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    /// The highest `memory_used` seen so far.
+    pub fn memory_peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Makes storing a value fail once `memory_used` would go over `max_memory`.
+    pub fn set_memory_limit(&mut self, max_memory: Option<usize>) {
+        self.max_memory = max_memory;
+    }
+
+    /// Fails if `bytes` more could not be stored without going over the limit.
+    pub fn check_memory(&self, bytes: usize, line: u32) -> Result<(), Error> {
+        match self.max_memory {
+            Some(max_memory) if self.used + bytes > max_memory => Err(Error::budget(
+                line,
+                format!("memory use would exceed {} bytes.", max_memory),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for a binding growing from `old` to `new` bytes.
+    fn reserve(&mut self, old: usize, new: usize, line: u32) -> Result<(), Error> {
+        if new > old {
+            self.check_memory(new - old, line)?;
+        }
+        self.used = self.used + new - old;
+        self.peak = max(self.peak, self.used);
+        Ok(())
     }
 
     pub fn define(&mut self, name: &Token, value: Instance) -> Result<(), Error> {
-        let Token { kind, .. } = name;
+        let Token { kind, line, .. } = name;
         if let TokenKind::Identifier(var_name) = kind {
+            if let Some(const_line) = self.consts.get(var_name) {
+                return self.error(
+                    format!(
+                        "Cannot redefine constant '{}' declared on line {}.",
+                        var_name, const_line
+                    ),
+                    *line,
+                );
+            }
+            let old = self
+                .values
+                .get(var_name)
+                .map_or(0, |old| entry_size(var_name, old));
+            let new = entry_size(var_name, &value);
+            self.reserve(old, new, *line)?;
+            self.scope_bytes = self.scope_bytes + new - old;
             self.values.insert(var_name.to_string(), value);
             return Ok(());
         }
         unreachable!()
     }
 
+    pub fn define_const(&mut self, name: &Token, value: Instance) -> Result<(), Error> {
+        self.define(name, value)?;
+        if let TokenKind::Identifier(ref var_name) = name.kind {
+            self.consts.insert(var_name.to_string(), name.line);
+        }
+        Ok(())
+    }
+
     pub fn get(&self, name: &Token) -> Result<Instance, Error> {
-        let Token { kind, line } = name;
+        let Token { kind, line, .. } = name;
         if let TokenKind::Identifier(var_name) = kind {
             if let Some(ins) = self.values.get(var_name) {
                 return Ok(ins.clone());
@@ -62,37 +184,136 @@ impl Stack {
     }
 
     pub fn assign(&mut self, name: &Token, value: Instance) -> Result<Instance, Error> {
-        let Token { kind, line } = name;
+        let Token { kind, line, .. } = name;
         if let TokenKind::Identifier(var_name) = kind {
-            if let Some(ref mut entry) = self.values.get_mut(var_name) {
-                **entry = value.clone();
-                return Ok(value);
-            }
+            let old = match self.scope_of(var_name) {
+                Some(scope) => {
+                    if let Some(const_line) = scope.consts.get(var_name) {
+                        return self.error(
+                            format!(
+                                "Cannot assign to constant '{}' declared on line {}.",
+                                var_name, const_line
+                            ),
+                            *line,
+                        );
+                    }
+                    entry_size(var_name, &scope.values[var_name])
+                }
+                None => return self.error(format!("Undefined variable '{}'.", var_name), *line),
+            };
+            let new = entry_size(var_name, &value);
+            self.reserve(old, new, *line)?;
 
-            if let Some(ref mut stack) = self.next {
-                return stack.as_mut().assign(name, value);
+            if let Some(scope) = self.scope_of_mut(var_name) {
+                scope.scope_bytes = scope.scope_bytes + new - old;
+                scope.values.insert(var_name.to_string(), value.clone());
             }
-
-            return self.error(format!("Undefined variable '{}'.", var_name), *line);
+            return Ok(value);
         }
         unreachable!()
     }
 
+    /// The innermost scope binding `var_name`.
+    fn scope_of(&self, var_name: &str) -> Option<&Stack> {
+        if self.values.contains_key(var_name) {
+            return Some(self);
+        }
+        self.next
+            .as_ref()
+            .and_then(|stack| stack.scope_of(var_name))
+    }
+
+    fn scope_of_mut(&mut self, var_name: &str) -> Option<&mut Stack> {
+        if self.values.contains_key(var_name) {
+            return Some(self);
+        }
+        self.next
+            .as_mut()
+            .and_then(|stack| stack.scope_of_mut(var_name))
+    }
+
+    /// Bindings of this scope only, sorted by name.
+    pub fn bindings(&self) -> Vec<(&str, &Instance)> {
+        let mut bindings: Vec<(&str, &Instance)> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(b.0));
+        bindings
+    }
+
+    /// The enclosing scope, if any.
+    pub fn parent(&self) -> Option<&Stack> {
+        self.next.as_ref().map(|stack| stack.as_ref())
+    }
+
     fn raw_new(height: usize) -> Self {
         Self {
             values: HashMap::new(),
+            consts: HashMap::new(),
             height,
             next: None,
+            scope_bytes: SCOPE_SIZE,
+            used: SCOPE_SIZE,
+            peak: SCOPE_SIZE,
+            max_memory: None,
         }
     }
 
     fn error<T>(&self, msg: String, line: u32) -> Result<T, Error> {
-        Err(Error { line, msg })
+        Err(Error::new(line, msg))
     }
 }
 
+fn entry_size(name: &str, value: &Instance) -> usize {
+    name.len() + value.size()
+}
+
 impl Default for Stack {
     fn default() -> Self {
         Self::raw_new(0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(name: &str) -> Token {
+        Token::new(TokenKind::Identifier(name.into()), 1)
+    }
+
+    #[test]
+    fn memory_accounting() {
+        let mut stack = Stack::default();
+        let empty = stack.memory_used();
+        stack
+            .define(&name("a"), Instance::String("x".repeat(1000)))
+            .unwrap();
+        let with_a = stack.memory_used();
+        assert!(with_a >= empty + 1000);
+
+        stack.push();
+        stack.define(&name("b"), Instance::Nil).unwrap();
+        stack.assign(&name("a"), Instance::Nil).unwrap();
+        assert!(stack.memory_used() < with_a);
+        stack.pop();
+        assert_eq!(stack.memory_used(), empty + entry_size("a", &Instance::Nil));
+        assert!(stack.memory_peak() >= with_a);
+
+        stack.set_memory_limit(Some(with_a));
+        let e = stack
+            .assign(&name("a"), Instance::String("x".repeat(2000)))
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.msg,
+            format!(
+                "Budget exhausted: memory use would exceed {} bytes.",
+                with_a
+            )
+        );
+        assert!(stack.memory_used() < with_a);
+    }
+}
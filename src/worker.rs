@@ -1,18 +1,207 @@
+use ast::arm::MatchArm;
 use ast::expr::*;
 use ast::stmt::*;
-use error::Error;
+use error::{Error, ErrorKind};
 use function::callable::Callable;
 use function::*;
+use module::{compile, Modules};
 use scanner::token::{Token, TokenKind};
 use stack::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// How many steps run between two looks at the clock.
+const DEADLINE_INTERVAL: u64 = 256;
+
+/// How many steps `finally` blocks may run to clean up once the run has hit
+/// its limits or been interrupted.
+pub const FINALLY_STEPS: u64 = 10_000;
+
+/// How deep calls may nest unless configured otherwise. The native stack is
+/// guarded by `MAX_DEPTH`, as each call can hold many nested statements.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+/// How deep statements and expressions may nest while running, calls
+/// included. A level takes about 2.3 KiB of native stack in a debug build and
+/// 0.5 KiB in a release build, so either stays within half of the 8 MiB of a
+/// main thread.
+pub const MAX_DEPTH: usize = if cfg!(debug_assertions) { 1500 } else { 8000 };
+
+/// How control leaves a statement.
+#[derive(Debug, Clone)]
+pub enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Instance),
+}
+
+/// Observes a run from the inside, e.g. for a debugger. Installing none
+/// costs the normal path a single check per statement.
+pub trait Hook {
+    /// Called before each statement runs. An error stops the run.
+    fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error>;
+
+    /// Called when a call to a user or native function starts. `name` is the
+    /// declared name, or else the variable the function was called through.
+    fn before_call(&mut self, _name: &str) {}
+
+    /// Called when the call last started returns, successfully or not.
+    fn after_call(&mut self) {}
+
+    /// Called when a branch point is passed: an `if`, each test of a `while`
+    /// condition, a `match`, or the left operand of `and` or `or`. `line` and
+    /// `column` tell the points apart. `taken` counts the ways out from 0:
+    /// the `then` block or loop body, then the `else` block or loop exit; the
+    /// arms of a `match` in order, then no arm; running the right operand of
+    /// `and` or `or`, then skipping it.
+    fn branch(&mut self, _worker: &Worker, _line: u32, _column: u32, _taken: usize) {}
+}
+
+/// A user function call in progress.
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// The function's name, `<lambda>` for anonymous ones.
+    pub name: String,
+    /// The line the call was made from.
+    pub line: u32,
+    /// The module the function was declared in.
+    pub file: Option<PathBuf>,
+    /// The height of the scope holding the function's parameters.
+    pub height: usize,
+}
 
 pub struct Worker<'a> {
     pub stack: &'a mut Stack,
+    modules: Option<&'a mut Modules>,
+    steps: u64,
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
+    calls: Vec<Call>,
+    max_call_depth: usize,
+    // statements and expressions being run, one inside the other
+    depth: usize,
+    // steps left to `finally` blocks after a limit or an interrupt
+    grace: Option<u64>,
+    cancel: Option<&'a AtomicBool>,
+    hook: Option<&'a mut dyn Hook>,
+    output: Option<&'a mut dyn Write>,
 }
 
 impl<'a> Worker<'a> {
     pub fn new(stack: &'a mut Stack) -> Self {
-        Self { stack }
+        Self {
+            stack,
+            modules: None,
+            steps: 0,
+            max_steps: None,
+            deadline: None,
+            calls: Vec::new(),
+            max_call_depth: MAX_CALL_DEPTH,
+            depth: 0,
+            grace: None,
+            cancel: None,
+            hook: None,
+            output: None,
+        }
+    }
+
+    /// Lets the program `import` modules tracked by `modules`.
+    pub fn with_modules(mut self, modules: &'a mut Modules) -> Self {
+        self.modules = Some(modules);
+        self
+    }
+
+    /// Stops the run with a budget error after `max_steps` statements and
+    /// expressions, or once `deadline` has passed.
+    pub fn with_limits(mut self, max_steps: Option<u64>, deadline: Option<Instant>) -> Self {
+        self.max_steps = max_steps;
+        self.deadline = deadline;
+        self
+    }
+
+    /// Fails calls nested deeper than `max_call_depth` with a stack overflow
+    /// error, before they can overflow the native stack.
+    pub fn with_max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = max_call_depth;
+        self
+    }
+
+    /// Stops the run with an "Interrupted" error once `cancel` is set.
+    pub fn with_cancel(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Lets `hook` look at every statement before it runs.
+    pub fn with_hook(mut self, hook: &'a mut dyn Hook) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Sends what `print` writes to `output` instead of stdout.
+    pub fn with_output(mut self, output: &'a mut dyn Write) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// How many user function calls are in progress.
+    pub fn call_depth(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// The user function calls in progress, outermost first.
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// The module being run, whichever function it is in. `None` for code
+    /// that does not come from a file.
+    pub fn module_file(&self) -> Option<&Path> {
+        self.modules.as_ref().and_then(|modules| modules.current())
+    }
+
+    /// The module declaring the function being called, else the module being
+    /// run. `None` for code that does not come from a file.
+    pub fn current_file(&self) -> Option<&Path> {
+        match self.calls.last() {
+            Some(call) => call.file.as_deref(),
+            None => self.module_file(),
+        }
+    }
+
+    /// Enters a call to the function `name` declared in `file`, made at
+    /// `paren`, before its scope is pushed. Each successful call must be
+    /// matched by `leave_call`.
+    pub fn enter_call(
+        &mut self,
+        name: &str,
+        file: Option<&Path>,
+        paren: &Token,
+    ) -> Result<(), Error> {
+        if self.calls.len() >= self.max_call_depth {
+            return self.error(
+                format!(
+                    "Stack overflow: calls to '{}' nested deeper than {}.",
+                    name, self.max_call_depth
+                ),
+                paren.line,
+            );
+        }
+        self.calls.push(Call {
+            name: name.to_string(),
+            line: paren.line,
+            file: file.map(Path::to_path_buf),
+            height: self.stack.height + 1,
+        });
+        Ok(())
+    }
+
+    pub fn leave_call(&mut self) {
+        self.calls.pop();
     }
 
     pub fn run(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
@@ -22,12 +211,171 @@ impl<'a> Worker<'a> {
         Ok(())
     }
 
-    pub fn execute(&mut self, stmt: &Stmt) -> Result<(), Error> {
-        stmt.accept(self)
+    /// Runs the statements like `run`, returning the value of the last one
+    /// when it is a bare expression. That statement is counted and shown to
+    /// the hook like any other.
+    pub fn run_last_value(&mut self, stmts: &[Stmt]) -> Result<Option<Instance>, Error> {
+        if let Some((last, rest)) = stmts.split_last() {
+            if let Stmt::Expression { expression } = last {
+                self.run(rest)?;
+                self.before_stmt(last)?;
+                return Ok(Some(self.evaluate(expression)?));
+            }
+        }
+        self.run(stmts)?;
+        Ok(None)
+    }
+
+    pub fn execute(&mut self, stmt: &Stmt) -> Result<Flow, Error> {
+        self.before_stmt(stmt)?;
+        if self.depth >= MAX_DEPTH {
+            return self.too_deep(stmt.line());
+        }
+        self.depth += 1;
+        let flow = stmt.accept(self);
+        self.depth -= 1;
+        flow
+    }
+
+    /// Counts `stmt` against the limits and shows it to the hook.
+    fn before_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.step(|| stmt.line())?;
+        if let Some(hook) = self.hook.take() {
+            let result = hook.before_stmt(self, stmt);
+            self.hook = Some(hook);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn execute_block(&mut self, statements: &[Stmt]) -> Result<Flow, Error> {
+        for stmt in statements {
+            match self.execute(stmt)? {
+                Flow::Normal => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Instance, Error> {
+        self.step(|| expr.line())?;
+        if self.depth >= MAX_DEPTH {
+            return self.too_deep(expr.line());
+        }
+        self.depth += 1;
+        let value = expr.accept(self);
+        self.depth -= 1;
+        value
+    }
+
+    /// Fails a statement or expression nested deeper than `MAX_DEPTH`.
+    fn too_deep<T>(&self, line: Option<u32>) -> Result<T, Error> {
+        let msg = format!(
+            "Stack overflow: statements and expressions nested deeper than {}.",
+            MAX_DEPTH
+        );
+        self.error(msg, line.unwrap_or_else(|| self.call_line()))
+    }
+
+    /// Counts one step against the limits and checks for cancellation. `line`
+    /// is only asked for when the run has to stop.
+    fn step<F: Fn() -> Option<u32>>(&mut self, line: F) -> Result<(), Error> {
+        self.steps += 1;
+        // an empty block or statement stops on the line of the current call
+        let line = || line().unwrap_or_else(|| self.call_line());
+        if let Some(left) = self.grace {
+            if left == 0 {
+                let msg = format!("cleanup ran more than {} steps.", FINALLY_STEPS);
+                return Err(Error::budget(line(), msg));
+            }
+            self.grace = Some(left - 1);
+            return Ok(());
+        }
+        if let Some(cancel) = self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Error::interrupted(line()));
+            }
+        }
+        if let Some(max_steps) = self.max_steps {
+            if self.steps > max_steps {
+                let msg = format!("ran more than {} steps.", max_steps);
+                return Err(Error::budget(line(), msg));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline {
+                let msg = "ran past its deadline.".to_string();
+                return Err(Error::budget(line(), msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the hook which way out of a branch point was taken. `at`, the
+    /// line and column of the point, is only asked for when there is a hook.
+    fn branch<F: Fn() -> (u32, u32)>(&mut self, at: F, taken: usize) {
+        if let Some(hook) = self.hook.take() {
+            let (line, column) = at();
+            hook.branch(self, line, column, taken);
+            self.hook = Some(hook);
+        }
+    }
+
+    /// The line the innermost call was made from, or 0 outside of calls.
+    fn call_line(&self) -> u32 {
+        self.calls.last().map_or(0, |call| call.line)
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<Instance, Error> {
-        expr.accept(self)
+    fn iterate(
+        &self,
+        line: u32,
+        value: Instance,
+    ) -> Result<Box<dyn Iterator<Item = Instance>>, Error> {
+        let iter: Option<Box<dyn Iterator<Item = Instance>>> = match value {
+            Instance::String(ref s) => Some(Box::new(
+                s.chars()
+                    .collect::<Vec<char>>()
+                    .into_iter()
+                    .map(|c| Instance::String(c.to_string())),
+            )),
+            Instance::Function(ref fun) => fun.iter(),
+            _ => None,
+        };
+        match iter {
+            Some(iter) => Ok(iter),
+            None => self.error(
+                format!("Expected an iterable, found '{:?}' instead.", value),
+                line,
+            ),
+        }
+    }
+
+    fn match_arm(
+        &mut self,
+        keyword: &Token,
+        value: &Instance,
+        arm: &MatchArm,
+    ) -> Result<bool, Error> {
+        let mut matched = arm.is_default();
+        for pattern in arm.patterns.iter() {
+            let pattern = self.evaluate(pattern)?;
+            if let Instance::Bool(true) = self.primitive_eq(keyword, value, &pattern)? {
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            return Ok(false);
+        }
+
+        match arm.guard {
+            Some(ref guard) => match self.evaluate(guard)? {
+                Instance::Bool(con) => Ok(con),
+                other => self.condition_error("Match", &keyword.line, &other),
+            },
+            None => Ok(true),
+        }
     }
 
     fn primitive_not(&mut self, operator: &Token, value: &Instance) -> Result<Instance, Error> {
@@ -56,7 +404,11 @@ impl<'a> Worker<'a> {
                 _ => self.binary_error(operator, "Number", value1, value2),
             },
             Instance::String(ref v1) => match value2 {
-                Instance::String(v2) => Ok(Instance::String(format!("{}{}", v1, v2))),
+                Instance::String(v2) => {
+                    self.stack
+                        .check_memory(v1.len() + v2.len(), operator.line)?;
+                    Ok(Instance::String(format!("{}{}", v1, v2)))
+                }
                 _ => self.binary_error(operator, "String", value1, value2),
             },
             _ => self.binary_error(operator, "Number | String", value1, value2),
@@ -164,6 +516,7 @@ impl<'a> Worker<'a> {
             Instance::Bool(value1) => value1,
             value1 => return self.unary_error(operator, "Bool", &value1),
         };
+        self.branch(|| (operator.line, operator.column), value1 as usize);
         if value1 {
             Ok(Instance::Bool(value1))
         } else {
@@ -184,6 +537,7 @@ impl<'a> Worker<'a> {
             Instance::Bool(value1) => value1,
             value1 => return self.unary_error(operator, "Bool", &value1),
         };
+        self.branch(|| (operator.line, operator.column), !value1 as usize);
         if !value1 {
             Ok(Instance::Bool(value1))
         } else {
@@ -216,7 +570,7 @@ impl<'a> Worker<'a> {
     }
 
     fn operator_error(&self, operator: &Token, operator_kind: &str) -> Result<Instance, Error> {
-        let Token { kind, line } = operator;
+        let Token { kind, line, .. } = operator;
         self.error(
             format!("{:?} operator is not a {} operator.", kind, operator_kind),
             *line,
@@ -229,7 +583,7 @@ impl<'a> Worker<'a> {
         expected: &str,
         value: &Instance,
     ) -> Result<Instance, Error> {
-        let Token { kind, line } = operator;
+        let Token { kind, line, .. } = operator;
         self.error(
             format!(
                 "{:?} operator expected type '{}', found '{:?}' instead.",
@@ -239,12 +593,12 @@ impl<'a> Worker<'a> {
         )
     }
 
-    fn condition_error(
+    fn condition_error<T>(
         &self,
         statement_kind: &str,
         line: &u32,
         value: &Instance,
-    ) -> Result<(), Error> {
+    ) -> Result<T, Error> {
         self.error(
             format!(
                 "{} statement condition must be 'Bool', found '{:?}' instead.",
@@ -261,7 +615,7 @@ impl<'a> Worker<'a> {
         value1: &Instance,
         value2: &Instance,
     ) -> Result<Instance, Error> {
-        let Token { kind, line } = operator;
+        let Token { kind, line, .. } = operator;
         self.error(
             format!(
                 "{:?} operator expected type '{}', found '{:?}' and '{:?}' instead.",
@@ -272,7 +626,7 @@ impl<'a> Worker<'a> {
     }
 
     fn error<T>(&self, msg: String, line: u32) -> Result<T, Error> {
-        Err(Error { line, msg })
+        Err(Error::new(line, msg))
     }
 }
 
@@ -334,11 +688,11 @@ impl<'a> ExprVisitor<Result<Instance, Error>> for Worker<'a> {
 
     fn visit_call(
         &mut self,
-        callee: &Expr,
+        callee_expr: &Expr,
         paren: &Token,
         arguments: &[Expr],
     ) -> Result<Instance, Error> {
-        let callee = self.evaluate(callee)?;
+        let callee = self.evaluate(callee_expr)?;
         let mut unpacked_arg: Vec<Instance> = Vec::new();
 
         for arg in arguments {
@@ -346,7 +700,31 @@ impl<'a> ExprVisitor<Result<Instance, Error>> for Worker<'a> {
         }
 
         if let Instance::Function(fun) = callee {
-            fun.call(self, paren, &unpacked_arg)
+            if self.hook.is_none() {
+                return fun.call(self, paren, &unpacked_arg);
+            }
+            let name = match (fun.name(), callee_expr) {
+                (Some(name), _) => name.to_string(),
+                (
+                    None,
+                    Expr::Variable {
+                        name:
+                            Token {
+                                kind: TokenKind::Identifier(name),
+                                ..
+                            },
+                    },
+                ) => name.clone(),
+                _ => "<anonymous>".to_string(),
+            };
+            if let Some(ref mut hook) = self.hook {
+                hook.before_call(&name);
+            }
+            let result = fun.call(self, paren, &unpacked_arg);
+            if let Some(ref mut hook) = self.hook {
+                hook.after_call();
+            }
+            result
         } else {
             self.error(
                 format!("Expected a function, found '{:?}' instead", callee),
@@ -356,7 +734,7 @@ impl<'a> ExprVisitor<Result<Instance, Error>> for Worker<'a> {
     }
 
     fn visit_literal(&mut self, value: &Token) -> Result<Instance, Error> {
-        let Token { kind, line } = value;
+        let Token { kind, line, .. } = value;
 
         let ins = match kind {
             TokenKind::NumberLiteral(value_string) => match value_string.parse::<f64>() {
@@ -380,24 +758,107 @@ impl<'a> ExprVisitor<Result<Instance, Error>> for Worker<'a> {
         self.stack.get(name)
     }
 
+    fn visit_lambda(
+        &mut self,
+        keyword: &Token,
+        params: &[Token],
+        body: &Stmt,
+    ) -> Result<Instance, Error> {
+        let fun = AulUserFunction::new(keyword, params, body, self.current_file());
+        Ok(Instance::Function(Box::new(fun)))
+    }
+
+    fn visit_get(&mut self, object: &Expr, name: &Token) -> Result<Instance, Error> {
+        let object = self.evaluate(object)?;
+        let property = match name.kind {
+            TokenKind::Identifier(ref property) => property.as_str(),
+            _ => unreachable!(),
+        };
+        match (&object, property) {
+            (Instance::Error { message, .. }, "message") => Ok(Instance::String(message.clone())),
+            (Instance::Error { line, .. }, "line") => Ok(Instance::Number(f64::from(*line))),
+            (Instance::Error { .. }, _) => {
+                self.error(format!("Undefined property '{}'.", property), name.line)
+            }
+            _ => self.error(
+                format!("Only errors have properties, found '{:?}'.", object),
+                name.line,
+            ),
+        }
+    }
+
     fn visit_empty_expr(&mut self) -> Result<Instance, Error> {
         self.error("Found empty Expr.".into(), 0)
     }
 }
 
-impl<'a> StmtVisitor<Result<(), Error>> for Worker<'a> {
-    fn visit_var(&mut self, name: &Token, initializer: &Expr) -> Result<(), Error> {
+impl<'a> StmtVisitor<Result<Flow, Error>> for Worker<'a> {
+    fn visit_var(&mut self, name: &Token, initializer: &Expr) -> Result<Flow, Error> {
         let value = self.evaluate(initializer)?;
-        self.stack.define(name, value)
+        self.stack.define(name, value)?;
+        Ok(Flow::Normal)
     }
 
-    fn visit_block(&mut self, statements: &[Stmt]) -> Result<(), Error> {
-        self.stack.push();
-        for stmt in statements {
-            self.execute(stmt)?;
+    fn visit_const(&mut self, name: &Token, initializer: &Expr) -> Result<Flow, Error> {
+        let value = self.evaluate(initializer)?;
+        self.stack.define_const(name, value)?;
+        Ok(Flow::Normal)
+    }
+
+    fn visit_import(&mut self, keyword: &Token, path: &Token) -> Result<Flow, Error> {
+        let name = match path.kind {
+            TokenKind::StringLiteral(ref name) => name,
+            _ => unreachable!(),
+        };
+        let importer = self.current_file().map(Path::to_path_buf);
+        let file = match self.modules {
+            Some(ref modules) => match modules.resolve(name, importer.as_deref()) {
+                Some(file) => {
+                    if let Some(chain) = modules.cycle(&file) {
+                        return self.error(format!("Import cycle: {}", chain), keyword.line);
+                    }
+                    if modules.is_loaded(&file) {
+                        return Ok(Flow::Normal);
+                    }
+                    file
+                }
+                None => return self.error(format!("Cannot find module '{}'.", name), keyword.line),
+            },
+            None => return self.error("Imports are not available here.".into(), keyword.line),
+        };
+
+        let code = match fs::read_to_string(&file) {
+            Ok(code) => code,
+            Err(e) => {
+                return self.error(
+                    format!("Cannot read module '{}': {}", name, e),
+                    keyword.line,
+                )
+            }
+        };
+        let in_module = |e: Error| Error {
+            line: keyword.line,
+            msg: format!("in '{}' line {}: {}", name, e.line, e.msg),
+            ..e
+        };
+        let stmts = compile(code).map_err(in_module)?;
+
+        if let Some(ref mut modules) = self.modules {
+            modules.enter(file);
+        }
+        let result = self.run(&stmts);
+        if let Some(ref mut modules) = self.modules {
+            modules.leave(result.is_ok());
         }
+        result.map_err(in_module)?;
+        Ok(Flow::Normal)
+    }
+
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<Flow, Error> {
+        self.stack.push();
+        let flow = self.execute_block(statements);
         self.stack.pop();
-        Ok(())
+        flow
     }
 
     fn visit_if(
@@ -406,9 +867,11 @@ impl<'a> StmtVisitor<Result<(), Error>> for Worker<'a> {
         condition: &Expr,
         then_block: &Stmt,
         else_block: &Stmt,
-    ) -> Result<(), Error> {
+    ) -> Result<Flow, Error> {
         match self.evaluate(condition)? {
             Instance::Bool(con) => {
+                let at = || (*line_number, condition.column().unwrap_or(0));
+                self.branch(at, !con as usize);
                 if con {
                     self.execute(then_block)
                 } else {
@@ -424,20 +887,73 @@ impl<'a> StmtVisitor<Result<(), Error>> for Worker<'a> {
         line_number: &u32,
         condition: &Expr,
         body: &Stmt,
-    ) -> Result<(), Error> {
+        increment: &Stmt,
+    ) -> Result<Flow, Error> {
         loop {
             match self.evaluate(condition)? {
                 Instance::Bool(con) => {
-                    if con {
-                        self.execute(body)?;
-                    } else {
+                    let at = || (*line_number, condition.column().unwrap_or(0));
+                    self.branch(at, !con as usize);
+                    if !con {
                         break;
                     }
+                    match self.execute(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => (),
+                    }
+                    self.execute(increment)?;
                 }
-                other => self.condition_error("While", line_number, &other)?,
+                other => return self.condition_error("While", line_number, &other),
             }
         }
-        Ok(())
+        Ok(Flow::Normal)
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> Result<Flow, Error> {
+        let value = self.evaluate(iterable)?;
+        let iter = self.iterate(name.line, value)?;
+
+        for value in iter {
+            self.stack.push();
+            let flow = match self.stack.define(name, value) {
+                Ok(()) => self.execute(body),
+                Err(e) => Err(e),
+            };
+            self.stack.pop();
+            match flow? {
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => (),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn visit_match(
+        &mut self,
+        keyword: &Token,
+        value: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<Flow, Error> {
+        let value = self.evaluate(value)?;
+        let at = || (keyword.line, keyword.column);
+        for (i, arm) in arms.iter().enumerate() {
+            if self.match_arm(keyword, &value, arm)? {
+                self.branch(at, i);
+                return self.execute(&arm.body);
+            }
+        }
+        self.branch(at, arms.len());
+        Ok(Flow::Normal)
+    }
+
+    fn visit_break(&mut self, _keyword: &Token) -> Result<Flow, Error> {
+        Ok(Flow::Break)
+    }
+
+    fn visit_continue(&mut self, _keyword: &Token) -> Result<Flow, Error> {
+        Ok(Flow::Continue)
     }
 
     fn visit_function(
@@ -445,32 +961,449 @@ impl<'a> StmtVisitor<Result<(), Error>> for Worker<'a> {
         name: &Token,
         params: &[Token],
         body: &Stmt,
-    ) -> Result<(), Error> {
-        let fun = AulUserFunction::new(name, params, body);
+    ) -> Result<Flow, Error> {
+        let fun = AulUserFunction::new(name, params, body, self.current_file());
         let wrapped_fun = Box::new(fun) as Box<dyn Callable>;
         self.stack.define(name, Instance::Function(wrapped_fun))?;
-        Ok(())
+        Ok(Flow::Normal)
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: &Expr) -> Result<Flow, Error> {
+        let value = self.evaluate(value)?;
+        Ok(Flow::Return(value))
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, Error> {
+        match self.evaluate(value)? {
+            // rethrowing a caught error reports it like the original
+            Instance::Error { message, line } => Err(Error::new(line, message)),
+            value => Err(Error::thrown(keyword.line, value)),
+        }
+    }
+
+    fn visit_try(
+        &mut self,
+        _keyword: &Token,
+        body: &Stmt,
+        catch_name: &Option<Token>,
+        catch_body: &Stmt,
+        finally_body: &Stmt,
+    ) -> Result<Flow, Error> {
+        let result = match (self.execute(body), catch_name) {
+            (Err(e), Some(name)) if e.is_catchable() => {
+                self.stack.push();
+                let caught = match self.stack.define(name, e.into_value()) {
+                    Ok(()) => self.execute(catch_body),
+                    Err(e) => Err(e),
+                };
+                self.stack.pop();
+                caught
+            }
+            (result, _) => result,
+        };
+
+        match result {
+            // exits and exhausted limits still end the run after cleaning up,
+            // whatever `finally` does
+            Err(ref e) if !e.is_catchable() => {
+                let limited = matches!(e.kind, ErrorKind::Budget | ErrorKind::Interrupted);
+                if limited && self.grace.is_none() {
+                    // past the limits, cleaning up gets steps of its own
+                    self.grace = Some(FINALLY_STEPS);
+                    self.execute(finally_body).ok();
+                    self.grace = None;
+                } else {
+                    self.execute(finally_body).ok();
+                }
+                result
+            }
+            // a break, return or error from `finally` replaces the outcome
+            _ => match self.execute(finally_body)? {
+                Flow::Normal => result,
+                flow => Ok(flow),
+            },
+        }
     }
 
-    fn visit_expression(&mut self, expression: &Expr) -> Result<(), Error> {
+    fn visit_expression(&mut self, expression: &Expr) -> Result<Flow, Error> {
         self.evaluate(expression)?;
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn visit_print(&mut self, expression: &Expr) -> Result<(), Error> {
+    fn visit_print(&mut self, expression: &Expr) -> Result<Flow, Error> {
         let value = self.evaluate(expression)?;
-        match value {
-            Instance::String(s) => println!("{}", s),
-            Instance::Number(n) => println!("{}", n),
-            Instance::Bool(b) => println!("{}", b),
-            Instance::Function(_) => println!("function"),
-            Instance::Nil => println!("nil"),
+        match self.output {
+            Some(ref mut output) => {
+                // one write per line, so that it arrives in one piece
+                if let Err(e) = output.write_all(format!("{}\n", value).as_bytes()) {
+                    let line = expression.line().unwrap_or_else(|| self.call_line());
+                    return self.error(format!("Cannot write output: {}.", e), line);
+                }
+            }
+            None => println!("{}", value),
         }
+        Ok(Flow::Normal)
+    }
 
-        Ok(())
+    fn visit_empty_stmt(&mut self) -> Result<Flow, Error> {
+        Ok(Flow::Normal)
     }
+}
 
-    fn visit_empty_stmt(&mut self) -> Result<(), Error> {
-        Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+    use api::load_std_api;
+    use api::process::load_process_api;
+    use std::env;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn try_run(code: &str) -> Result<Stack, Error> {
+        let stmts = compile(code.into())?;
+        let mut stack = Stack::default();
+        load_std_api(&mut stack)?;
+        Worker::new(&mut stack).run(&stmts)?;
+        Ok(stack)
+    }
+
+    fn run(code: &str) -> Stack {
+        try_run(code).unwrap()
+    }
+
+    fn string(stack: &Stack, name: &str) -> String {
+        let token = Token::new(TokenKind::Identifier(name.into()), 0);
+        match stack.get(&token).unwrap() {
+            Instance::String(s) => s,
+            other => panic!("'{}' is not a string: {:?}", name, other),
+        }
+    }
+
+    fn number(stack: &Stack, name: &str) -> f64 {
+        let token = Token::new(TokenKind::Identifier(name.into()), 0);
+        match stack.get(&token).unwrap() {
+            Instance::Number(n) => n,
+            other => panic!("'{}' is not a number: {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let stack = run("
+            var a = 0;
+            while (true) {
+                a = a + 1;
+                if (a == 5) break;
+            }
+            var b = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                if ((i == 3) or (i == 7)) continue;
+                b = b + 1;
+            }
+        ");
+        assert_eq!(number(&stack, "a"), 5.0);
+        assert_eq!(number(&stack, "b"), 8.0);
+    }
+
+    #[test]
+    fn for_in() {
+        let stack = run("
+            var sum = 0;
+            for (i in range(5)) sum = sum + i;
+            var down = 0;
+            for (i in range(10, 0, -3)) down = down + i;
+            var s = \"\";
+            for (c in \"abc\") {
+                if (c == \"b\") continue;
+                s = c + s;
+            }
+        ");
+        assert_eq!(number(&stack, "sum"), 10.0);
+        assert_eq!(number(&stack, "down"), 22.0);
+        assert_eq!(string(&stack, "s"), "ca");
+    }
+
+    #[test]
+    fn lambda() {
+        let stack = run("
+            fun apply(f, x) { return f(x); }
+            var double = (a) => a * 2;
+            var a = apply(double, 4);
+            var b = apply(fun (n) { if (n > 1) return n - 1; return 0; }, 5);
+            var c = (() => 7)();
+        ");
+        assert_eq!(number(&stack, "a"), 8.0);
+        assert_eq!(number(&stack, "b"), 4.0);
+        assert_eq!(number(&stack, "c"), 7.0);
+    }
+
+    #[test]
+    fn match_arms() {
+        let stack = run("
+            fun rule(kind, n) {
+                match (kind) {
+                    \"a\", \"b\" => return 1;
+                    \"c\" if n > 0 => return 2;
+                    \"c\" => return 3;
+                    -1 => return 4;
+                    _ => return 5;
+                }
+            }
+            var a = rule(\"b\", 0);
+            var b = rule(\"c\", 1);
+            var c = rule(\"c\", 0);
+            var d = rule(-1, 0);
+            var e = rule(\"x\", 0);
+        ");
+        assert_eq!(number(&stack, "a"), 1.0);
+        assert_eq!(number(&stack, "b"), 2.0);
+        assert_eq!(number(&stack, "c"), 3.0);
+        assert_eq!(number(&stack, "d"), 4.0);
+        assert_eq!(number(&stack, "e"), 5.0);
+    }
+
+    #[test]
+    fn const_at_runtime() {
+        // functions see the caller's scope, which only the runtime check covers
+        let e = try_run("fun set() { x = 2; }\nconst x = 1;\n{ set(); }")
+            .err()
+            .unwrap();
+        assert_eq!(e.line, 1);
+        assert_eq!(e.msg, "Cannot assign to constant 'x' declared on line 2.");
+    }
+
+    #[test]
+    fn try_catch_finally() {
+        let stack = run("
+            var log = \"\";
+            var line = 0;
+            var msg = nil;
+            try {
+                log = log + \"a\";
+                var x = 1 + nil;
+                log = log + \"b\";
+            } catch (e) {
+                log = log + \"c\";
+                line = e.line;
+                msg = e.message;
+            } finally {
+                log = log + \"d\";
+            }
+
+            fun f() {
+                try {
+                    return \"try\";
+                } finally {
+                    log = log + \"e\";
+                }
+            }
+            var r = f();
+
+            var thrown = nil;
+            try {
+                try { throw \"x\"; } finally { log = log + \"f\"; }
+            } catch (e) {
+                thrown = e;
+            }
+
+            var i = 0;
+            var cleanups = 0;
+            while (i < 3) {
+                i = i + 1;
+                try { continue; } finally { cleanups = cleanups + 1; }
+            }
+        ");
+        assert_eq!(string(&stack, "log"), "acdef");
+        assert_eq!(number(&stack, "line"), 7.0);
+        assert_eq!(number(&stack, "cleanups"), 3.0);
+        assert!(string(&stack, "msg").starts_with("Plus operator expected"));
+        assert_eq!(string(&stack, "r"), "try");
+        assert_eq!(string(&stack, "thrown"), "x");
+
+        let e = try_run("\ntry { throw 42; } catch (e) { throw e + 1; }")
+            .err()
+            .unwrap();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.msg, "Uncaught exception: 43");
+        let e = try_run("try { x; } catch (e) {\nthrow e; }").err().unwrap();
+        assert_eq!((e.line, e.msg.as_str()), (1, "Undefined variable 'x'."));
+    }
+
+    #[test]
+    fn finally_on_exit() {
+        let code = "var log = \"\";
+                    fun f() {
+                        try { exit(2); } finally { log = log + \"f\"; return 1; }
+                    }
+                    try { f(); } catch (e) { log = log + \"c\"; } finally { log = log + \"g\"; }
+                    log = log + \"after\";";
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+        load_std_api(&mut stack).unwrap();
+        load_process_api(&mut stack, &[]).unwrap();
+        stack.push();
+
+        let e = Worker::new(&mut stack).run(&stmts).err().unwrap();
+        assert!(matches!(e.kind, ErrorKind::Exit(2)));
+        assert_eq!(string(&stack, "log"), "fg");
+    }
+
+    #[test]
+    fn finally_past_limits() {
+        let code = "var log = \"\";
+                    var n = 0;
+                    try {
+                        while (true) {}
+                    } finally {
+                        while (n < 100) n = n + 1;
+                        log = log + \"f\";
+                    }";
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+        stack.push();
+        let e = Worker::new(&mut stack)
+            .with_limits(Some(1000), None)
+            .run(&stmts)
+            .err()
+            .unwrap();
+        assert_eq!(e.msg, "Budget exhausted: ran more than 1000 steps.");
+        assert_eq!(string(&stack, "log"), "f");
+        assert_eq!(number(&stack, "n"), 100.0);
+
+        // cleanup that never ends is stopped too
+        let stmts = compile("try { while (true) {} } finally { while (true) {} }".into()).unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let interrupt = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let e = Worker::new(&mut stack)
+            .with_cancel(&cancel)
+            .run(&stmts)
+            .err()
+            .unwrap();
+        interrupt.join().unwrap();
+        assert!(matches!(e.kind, ErrorKind::Interrupted));
+    }
+
+    /// Records the line of each statement run, with the file it is in and
+    /// the module being run.
+    #[derive(Default)]
+    struct Files(Vec<(u32, String, String)>);
+
+    impl Hook for Files {
+        fn before_stmt(&mut self, worker: &mut Worker, stmt: &Stmt) -> Result<(), Error> {
+            let name = |file: Option<&Path>| {
+                file.and_then(Path::file_name)
+                    .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+            };
+            let line = stmt.line().unwrap_or(0);
+            let files = (name(worker.current_file()), name(worker.module_file()));
+            self.0.push((line, files.0, files.1));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn current_file() {
+        let dir = env::temp_dir().join("wendell_worker_file_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("util.aul"), "fun twice(x) {\n  return x * 2;\n}").unwrap();
+        let main = dir.join("main.aul");
+        let code = "import \"util.aul\";\nvar a = twice(3);";
+        fs::write(&main, code).unwrap();
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+        stack.push();
+        let mut modules = Modules::default();
+        modules.enter(main.canonicalize().unwrap());
+
+        let mut files = Files::default();
+        Worker::new(&mut stack)
+            .with_modules(&mut modules)
+            .with_hook(&mut files)
+            .run(&stmts)
+            .unwrap();
+        let expected: Vec<(u32, String, String)> = vec![
+            (1, "main.aul", "main.aul"),
+            (1, "util.aul", "util.aul"),
+            (2, "main.aul", "main.aul"),
+            (2, "util.aul", "main.aul"),
+            (2, "util.aul", "main.aul"),
+        ]
+        .into_iter()
+        .map(|(line, file, module)| (line, file.into(), module.into()))
+        .collect();
+        assert_eq!(files.0, expected);
+    }
+
+    #[test]
+    fn budget() {
+        let stmts = compile("var a = 0;\nwhile (true) {\n a = a + 1;\n}".into()).unwrap();
+        let mut stack = Stack::default();
+
+        let e = Worker::new(&mut stack)
+            .with_limits(Some(1000), None)
+            .run(&stmts)
+            .err()
+            .unwrap();
+        assert!(matches!(e.kind, ErrorKind::Budget));
+        assert_eq!(e.msg, "Budget exhausted: ran more than 1000 steps.");
+        assert!(e.line == 2 || e.line == 3);
+
+        let e = Worker::new(&mut stack)
+            .with_limits(None, Some(Instant::now()))
+            .run(&stmts)
+            .err()
+            .unwrap();
+        assert_eq!(e.msg, "Budget exhausted: ran past its deadline.");
+    }
+
+    #[test]
+    fn call_depth() {
+        let code = "fun f(n) { if (n > 0) { return f(n - 1); } return n; }\nvar a = f(40);\nvar b = f(60);";
+        let stmts = compile(code.into()).unwrap();
+        let mut stack = Stack::default();
+
+        let mut worker = Worker::new(&mut stack).with_max_call_depth(50);
+        let e = worker.run(&stmts).err().unwrap();
+        assert_eq!(e.line, 1);
+        assert_eq!(e.msg, "Stack overflow: calls to 'f' nested deeper than 50.");
+        assert_eq!(worker.call_depth(), 0);
+        assert_eq!(number(worker.stack, "a"), 0.0);
+    }
+
+    #[test]
+    fn nesting_depth() {
+        // each call nests 90 blocks, deeper in all than the native stack allows
+        let f = format!(
+            "fun f(n) {{ {} if (n > 0) return f(n - 1); {} return 0; }}\n",
+            "{".repeat(90),
+            "}".repeat(90)
+        );
+        // the limit is sized for the stack of a main thread
+        let results = thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || {
+                let e = try_run(&format!("{}f(199);", f)).err().unwrap();
+                let stack = run(&format!(
+                    "{}var caught = \"no\";\n\
+                     try {{ f(199); }} catch (e) {{ caught = \"yes\"; }}\n\
+                     var after = f(2);",
+                    f
+                ));
+                let caught = string(&stack, "caught");
+                (e.msg, caught, number(&stack, "after"))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(results
+            .0
+            .starts_with("Stack overflow: statements and expressions nested"));
+        assert_eq!(results.1, "yes");
+        assert_eq!(results.2, 0.0);
     }
 }
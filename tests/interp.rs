@@ -0,0 +1,25 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+fn status(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_interp"))
+        .args(args)
+        .output()
+        .expect("cannot run interp")
+        .status
+        .code()
+}
+
+#[test]
+fn exit_statuses() {
+    assert_eq!(status(&["-e", "print 1;"]), Some(0));
+    assert_eq!(status(&["-e", "print 1"]), Some(65));
+    assert_eq!(status(&["-e", "undefined;"]), Some(70));
+    assert_eq!(status(&["-e", "exit(3);"]), Some(3));
+    assert_eq!(status(&["--verbose"]), Some(64));
+
+    let missing = env::temp_dir().join("wendell_missing_script.aul");
+    fs::remove_file(&missing).ok();
+    assert_eq!(status(&[missing.to_str().unwrap()]), Some(66));
+}